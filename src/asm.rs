@@ -0,0 +1,878 @@
+// Assembler for Octo source (https://github.com/JohnEarnest/Octo). Covers
+// the statements and directives real programs use: labels, :alias, :const,
+// :macro, :org, :byte, :unpack, if/then, if/begin/else/end, loop/while/again
+// and raw numbers as sprite data. :calc and string modes are not supported.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::chip8::PROGRAM_START;
+
+// A macro calling itself would otherwise expand forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+// Words that can't be used as label, const, alias or macro names
+const RESERVED: &[&str] = &[
+    ":", ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "-", "{", "}", ";", "return", "clear", "bcd", "save", "load", "saveflags", "loadflags",
+    "sprite", "jump", "jump0", "if", "then", "begin", "else", "end", "loop", "again", "while",
+    "key", "-key", "i", "random", "delay", "buzzer", "hex", "bighex", "long", "hires", "lores",
+    "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit", "plane",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Result of assembling a program
+pub struct Assembly {
+    // Bytes to load at 0x200
+    pub rom: Vec<u8>,
+    // Label addresses by name
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    // One "ADDR name" line per label, sorted by address
+    pub fn write_symbols(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, addr)| (*addr, name));
+        for (name, addr) in labels {
+            writeln!(out, "{:04X} {}", addr, name)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut asm = Assembler::new(source);
+    asm.run()?;
+    Ok(Assembly { rom: asm.rom, labels: asm.labels })
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum FixupKind {
+    // Low 12 bits of the instruction at pos
+    Addr,
+    // The whole word at pos, for i := long
+    Long,
+    // v0 := nibble and high bits, v1 := low byte, for :unpack. Without
+    // long the nibble takes the top 4 bits, so the address must fit in 12.
+    Unpack { nibble: u16, long: bool },
+}
+
+// A reference to a label that wasn't defined yet when it was used
+struct Fixup {
+    pos: usize,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+enum Block {
+    // Address of the jump over the body, patched by else and end
+    If { jump: usize, line: usize },
+    // Loop start and the jumps out of it from while
+    Loop { start: usize, breaks: Vec<usize>, line: usize },
+}
+
+struct Assembler {
+    // Remaining tokens, reversed so the next one is popped off the end
+    tokens: Vec<Token>,
+    // Line of the token taken last, for errors
+    line: usize,
+    rom: Vec<u8>,
+    // Address the next byte is written to
+    pc: usize,
+    labels: BTreeMap<String, u16>,
+    consts: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        for word in line.split_whitespace() {
+            // Comments run to the end of the line
+            if word.starts_with('#') {
+                break;
+            }
+            tokens.push(Token { text: word.to_string(), line: n + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(c), None) => c.to_digit(16).map(|r| r as u8),
+        _ => None,
+    }
+}
+
+// Turns a skip taken when a condition is false into one taken when it's true
+fn invert_skip(op: u16) -> u16 {
+    match op & 0xF000 {
+        0x3000 => (op & 0x0FFF) | 0x4000,
+        0x4000 => (op & 0x0FFF) | 0x3000,
+        0x5000 => (op & 0x0FFF) | 0x9000,
+        0x9000 => (op & 0x0FFF) | 0x5000,
+        // Ex9E <-> ExA1
+        _ => if op & 0xFF == 0x9E { (op & 0xFF00) | 0xA1 } else { (op & 0xFF00) | 0x9E },
+    }
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let mut tokens = tokenize(source);
+        tokens.reverse();
+        Self {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            pc: PROGRAM_START,
+            labels: BTreeMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn err<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, message })
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.err("unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != text {
+            return self.err(format!("expected '{}', found '{}'", text, token));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let offset = self.pc - PROGRAM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.pc += 1;
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.emit_byte((word >> 8) as u8);
+        self.emit_byte(word as u8);
+    }
+
+    fn patch(&mut self, pos: usize, word: u16) {
+        let offset = pos - PROGRAM_START;
+        self.rom[offset] = (word >> 8) as u8;
+        self.rom[offset + 1] = word as u8;
+    }
+
+    fn word_at(&self, pos: usize) -> u16 {
+        let offset = pos - PROGRAM_START;
+        ((self.rom[offset] as u16) << 8) | self.rom[offset + 1] as u16
+    }
+
+    // The main label is where execution starts. Unless it's the first
+    // thing in the program, 0x200 holds a jump to it.
+    fn run(&mut self) -> Result<(), AsmError> {
+        let texts: Vec<&str> = self.tokens.iter().rev().map(|t| t.text.as_str()).collect();
+        let has_main = texts.windows(2).any(|w| w == [":", "main"]);
+        if has_main && !texts.starts_with(&[":", "main"]) {
+            self.fixups.push(Fixup { pos: self.pc, name: "main".to_string(), kind: FixupKind::Addr, line: 1 });
+            self.emit(0x1000);
+        }
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let (line, what) = match block {
+                Block::If { line, .. } => (*line, "'begin' without 'end'"),
+                Block::Loop { line, .. } => (*line, "'loop' without 'again'"),
+            };
+            return Err(AsmError { line, message: what.to_string() });
+        }
+        self.resolve_fixups()
+    }
+
+    fn resolve_fixups(&mut self) -> Result<(), AsmError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(&fixup.name) {
+                Some(&addr) => addr,
+                None => return Err(AsmError {
+                    line: fixup.line,
+                    message: format!("undefined label '{}'", fixup.name),
+                }),
+            };
+            match fixup.kind {
+                FixupKind::Addr => {
+                    if addr > 0xFFF {
+                        return Err(AsmError {
+                            line: fixup.line,
+                            message: format!("label '{}' at {:#06X} is out of range, use 'i := long'", fixup.name, addr),
+                        });
+                    }
+                    let word = self.word_at(fixup.pos);
+                    self.patch(fixup.pos, (word & 0xF000) | addr);
+                }
+                FixupKind::Long => self.patch(fixup.pos, addr),
+                FixupKind::Unpack { nibble, long } => {
+                    if !long && addr > 0xFFF {
+                        return Err(AsmError {
+                            line: fixup.line,
+                            message: format!("label '{}' at {:#06X} is out of range, use ':unpack long'", fixup.name, addr),
+                        });
+                    }
+                    self.patch(fixup.pos, 0x6000 | (nibble << 4) | (addr >> 8));
+                    self.patch(fixup.pos + 2, 0x6100 | (addr & 0xFF));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), AsmError> {
+        if RESERVED.contains(&name) || parse_number(name).is_some() || parse_register(name).is_some() || name.starts_with(':') {
+            return self.err(format!("'{}' can't be used as a name", name));
+        }
+        if self.labels.contains_key(name) || self.consts.contains_key(name)
+            || self.aliases.contains_key(name) || self.macros.contains_key(name) {
+            return self.err(format!("'{}' is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn lookup_register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        match self.lookup_register(&token) {
+            Some(r) => Ok(r),
+            None => self.err(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    // Next token is a register, without taking it
+    fn peek_register(&self) -> Option<u8> {
+        self.peek().and_then(|t| self.lookup_register(t))
+    }
+
+    fn lookup_value(&self, text: &str) -> Option<i32> {
+        parse_number(text)
+            .or_else(|| self.consts.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&a| a as i32))
+    }
+
+    fn value(&mut self, min: i32, max: i32) -> Result<i32, AsmError> {
+        let token = self.next()?;
+        match self.lookup_value(&token) {
+            Some(v) if v >= min && v <= max => Ok(v),
+            Some(v) => self.err(format!("{} is out of range ({} to {})", v, min, max)),
+            None => self.err(format!("undefined name '{}'", token)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, AsmError> {
+        Ok(self.value(-128, 255)? as u16 & 0xFF)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        Ok(self.value(0, 15)? as u16)
+    }
+
+    // An address operand for the instruction about to be emitted at pc.
+    // Labels that aren't defined yet are left to resolve_fixups.
+    fn address(&mut self, kind: FixupKind) -> Result<u16, AsmError> {
+        let max = match kind {
+            FixupKind::Addr | FixupKind::Unpack { long: false, .. } => 0xFFF,
+            _ => 0xFFFF,
+        };
+        let token = self.next()?;
+        match self.lookup_value(&token) {
+            Some(v) if v >= 0 && v <= max => Ok(v as u16),
+            Some(v) => self.err(format!("address {:#X} is out of range", v)),
+            None if parse_number(&token).is_none() && parse_register(&token).is_none() => {
+                self.fixups.push(Fixup { pos: self.pc, name: token, kind, line: self.line });
+                Ok(0)
+            }
+            None => self.err(format!("expected an address, found '{}'", token)),
+        }
+    }
+
+    // Instructions for a condition, ending in a skip taken when it's false
+    fn condition(&mut self) -> Result<Vec<u16>, AsmError> {
+        let x = self.register()? as u16;
+        let op = self.next()?;
+        match op.as_str() {
+            "key" => return Ok(vec![0xE0A1 | x << 8]),
+            "-key" => return Ok(vec![0xE09E | x << 8]),
+            _ => {}
+        }
+        let y = self.peek_register();
+        let ops = match (op.as_str(), y) {
+            ("==", Some(y)) => { self.next()?; vec![0x9000 | x << 8 | (y as u16) << 4] }
+            ("!=", Some(y)) => { self.next()?; vec![0x5000 | x << 8 | (y as u16) << 4] }
+            ("==", None) => vec![0x4000 | x << 8 | self.byte()?],
+            ("!=", None) => vec![0x3000 | x << 8 | self.byte()?],
+            // Comparisons go through VF: VF = rhs, then a subtraction leaves
+            // the NOT borrow flag in VF which is tested against 0 or 1
+            ("<" | ">" | "<=" | ">=", _) => {
+                let load = match y {
+                    Some(y) => { self.next()?; 0x8F00 | (y as u16) << 4 }
+                    None => 0x6F00 | self.byte()?,
+                };
+                let sub = if op == "<" || op == ">=" { 0x8F07 } else { 0x8F05 };
+                let test = if op == "<" || op == ">" { 0x4F00 } else { 0x4F01 };
+                vec![load, sub | x << 4, test]
+            }
+            _ => return self.err(format!("unknown comparison '{}'", op)),
+        };
+        Ok(ops)
+    }
+
+    // A condition followed by a jump over the block it guards
+    // JP to where a block starts or ends. Like any other jump it only has
+    // 12 bits for the address.
+    fn block_jump(&self, target: usize) -> Result<u16, AsmError> {
+        if target > 0xFFF {
+            return self.err(format!("jump target {:#06X} is out of range", target));
+        }
+        Ok(0x1000 | target as u16)
+    }
+
+    fn conditional_jump(&mut self, ops: Vec<u16>) -> usize {
+        let last = ops.len() - 1;
+        for (n, op) in ops.into_iter().enumerate() {
+            self.emit(if n == last { invert_skip(op) } else { op });
+        }
+        let jump = self.pc;
+        self.emit(0x1000);
+        jump
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.err(format!("too many macro expansions, is '{}' recursive?", name));
+        }
+        let line = self.line;
+        let mut args = HashMap::new();
+        for arg in self.macros[name].args.clone() {
+            args.insert(arg, self.next()?);
+        }
+        let body: Vec<Token> = self.macros[name].body.iter().rev().map(|t| Token {
+            text: args.get(&t.text).cloned().unwrap_or_else(|| t.text.clone()),
+            // Errors inside the body point at the invocation
+            line,
+        }).collect();
+        self.tokens.extend(body);
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), AsmError> {
+        match directive {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                if self.pc > 0xFFFF {
+                    return self.err(format!("label '{}' is past the end of memory", name));
+                }
+                self.labels.insert(name, self.pc as u16);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let r = self.register()?;
+                self.aliases.insert(name, r);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.value(-0x10000, 0xFFFF)?;
+                self.consts.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let mut args = Vec::new();
+                loop {
+                    let arg = self.next()?;
+                    if arg == "{" {
+                        break;
+                    }
+                    args.push(arg);
+                }
+                let mut body = Vec::new();
+                let mut depth = 1;
+                loop {
+                    let token = self.tokens.pop();
+                    let token = match token {
+                        Some(t) => t,
+                        None => return self.err(format!("macro '{}' is missing a closing '}}'", name)),
+                    };
+                    self.line = token.line;
+                    match token.text.as_str() {
+                        "{" => depth += 1,
+                        "}" => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    body.push(token);
+                }
+                self.macros.insert(name, Macro { args, body });
+            }
+            ":org" => {
+                let addr = self.value(PROGRAM_START as i32, 0xFFFF)?;
+                self.pc = addr as usize;
+            }
+            ":byte" => {
+                let b = self.byte()?;
+                self.emit_byte(b as u8);
+            }
+            ":unpack" => {
+                let token = self.next()?;
+                // long takes the whole address, otherwise the nibble goes on top
+                let (nibble, long) = match self.lookup_value(&token) {
+                    _ if token == "long" => (0, true),
+                    Some(v @ 0..=15) => (v as u16, false),
+                    _ => return self.err(format!("expected a nibble or 'long', found '{}'", token)),
+                };
+                let addr = self.address(FixupKind::Unpack { nibble, long })?;
+                let hi = (nibble << 4) | (addr >> 8);
+                self.emit(0x6000 | hi);
+                self.emit(0x6100 | (addr & 0xFF));
+            }
+            // Debugger hints for Octo, nothing to emit
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            _ => return self.err(format!("unknown directive '{}'", directive)),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let y = self.peek_register().map(|y| (y as u16) << 4);
+        if let Some(y) = y {
+            let alu = match op.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.err(format!("unknown operator '{}'", op)),
+            };
+            self.next()?;
+            self.emit(0x8000 | x << 8 | y | alu);
+            return Ok(());
+        }
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let mask = self.byte()?;
+                    self.emit(0xC000 | x << 8 | mask);
+                }
+                Some("delay") => {
+                    self.next()?;
+                    self.emit(0xF007 | x << 8);
+                }
+                Some("key") => {
+                    self.next()?;
+                    self.emit(0xF00A | x << 8);
+                }
+                _ => {
+                    let kk = self.byte()?;
+                    self.emit(0x6000 | x << 8 | kk);
+                }
+            },
+            "+=" => {
+                let kk = self.byte()?;
+                self.emit(0x7000 | x << 8 | kk);
+            }
+            "-=" => {
+                let kk = self.byte()?;
+                self.emit(0x7000 | x << 8 | (kk.wrapping_neg() & 0xFF));
+            }
+            _ => return self.err(format!("'{}' needs a register on the right", op)),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token.starts_with(':') {
+            return self.directive(&token);
+        }
+        if let Some(x) = self.lookup_register(&token) {
+            return self.register_statement(x as u16);
+        }
+        if let Some(value) = parse_number(&token) {
+            // Raw bytes, usually sprite data
+            if !(-128..=255).contains(&value) {
+                return self.err(format!("{} does not fit in a byte", value));
+            }
+            self.emit_byte(value as u8);
+            return Ok(());
+        }
+        match token.as_str() {
+            "return" | ";" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "hires" => self.emit(0x00FF),
+            "lores" => self.emit(0x00FE),
+            "exit" => self.emit(0x00FD),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "jump" => {
+                let nnn = self.address(FixupKind::Addr)?;
+                self.emit(0x1000 | nnn);
+            }
+            "jump0" => {
+                let nnn = self.address(FixupKind::Addr)?;
+                self.emit(0xB000 | nnn);
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let op = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | x << 8 | y << 4);
+                } else {
+                    let op = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | x << 8);
+                }
+            }
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.emit(0xF033 | x << 8);
+            }
+            "saveflags" => {
+                let x = self.register()? as u16;
+                self.emit(0xF075 | x << 8);
+            }
+            "loadflags" => {
+                let x = self.register()? as u16;
+                self.emit(0xF085 | x << 8);
+            }
+            "plane" => {
+                let n = self.value(0, 3)? as u16;
+                self.emit(0xF001 | n << 8);
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                self.emit(if token == "delay" { 0xF015 } else { 0xF018 } | x << 8);
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.as_str() {
+                    "+=" => {
+                        let x = self.register()? as u16;
+                        self.emit(0xF01E | x << 8);
+                    }
+                    ":=" => match self.peek() {
+                        Some("hex") => {
+                            self.next()?;
+                            let x = self.register()? as u16;
+                            self.emit(0xF029 | x << 8);
+                        }
+                        Some("bighex") => {
+                            self.next()?;
+                            let x = self.register()? as u16;
+                            self.emit(0xF030 | x << 8);
+                        }
+                        Some("long") => {
+                            self.next()?;
+                            self.emit(0xF000);
+                            let addr = self.address(FixupKind::Long)?;
+                            self.emit(addr);
+                        }
+                        _ => {
+                            let nnn = self.address(FixupKind::Addr)?;
+                            self.emit(0xA000 | nnn);
+                        }
+                    },
+                    _ => return self.err(format!("unknown operator '{}' for i", op)),
+                }
+            }
+            "if" => {
+                let ops = self.condition()?;
+                let line = self.line;
+                match self.next()?.as_str() {
+                    "then" => for op in ops {
+                        self.emit(op);
+                    },
+                    "begin" => {
+                        let jump = self.conditional_jump(ops);
+                        self.blocks.push(Block::If { jump, line });
+                    }
+                    other => return self.err(format!("expected 'then' or 'begin', found '{}'", other)),
+                }
+            }
+            "else" => {
+                let jump = match self.blocks.last() {
+                    Some(&Block::If { jump, .. }) => jump,
+                    _ => return self.err("'else' without 'begin'".to_string()),
+                };
+                let end_jump = self.pc;
+                self.emit(0x1000);
+                let word = self.block_jump(self.pc)?;
+                self.patch(jump, word);
+                if let Some(Block::If { jump, .. }) = self.blocks.last_mut() {
+                    *jump = end_jump;
+                }
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let word = self.block_jump(self.pc)?;
+                    self.patch(jump, word);
+                }
+                _ => return self.err("'end' without 'begin'".to_string()),
+            },
+            "loop" => {
+                let line = self.line;
+                self.blocks.push(Block::Loop { start: self.pc, breaks: Vec::new(), line });
+            }
+            "while" => {
+                let ops = self.condition()?;
+                let jump = self.conditional_jump(ops);
+                match self.blocks.iter_mut().rev().find(|b| matches!(b, Block::Loop { .. })) {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return self.err("'while' outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    let word = self.block_jump(start)?;
+                    self.emit(word);
+                    let word = self.block_jump(self.pc)?;
+                    for jump in breaks {
+                        self.patch(jump, word);
+                    }
+                }
+                _ => return self.err("'again' without 'loop'".to_string()),
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // Anything else is a subroutine call
+            _ => {
+                self.tokens.push(Token { text: token, line: self.line });
+                let nnn = self.address(FixupKind::Addr)?;
+                self.emit(0x2000 | nnn);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{Disassembly, LabelKind};
+
+    fn words(source: &str) -> Vec<u16> {
+        let rom = assemble(source).unwrap().rom;
+        rom.chunks(2).map(|w| ((w[0] as u16) << 8) | w[1] as u16).collect()
+    }
+
+    fn error_line(source: &str) -> usize {
+        assemble(source).err().expect("should not assemble").line
+    }
+
+    #[test]
+    fn if_then_skips_the_next_instruction() {
+        assert_eq!(words(": main if v0 == 5 then v1 := 2"), [0x4005, 0x6102]);
+        assert_eq!(words(": main if v0 != v3 then v1 := 2"), [0x5030, 0x6102]);
+        assert_eq!(words(": main if v2 key then v1 := 2"), [0xE2A1, 0x6102]);
+    }
+
+    #[test]
+    fn if_begin_else_end_jumps_around_the_blocks() {
+        let code = words(": main if v0 == 5 begin v1 := 1 else v1 := 2 end");
+        // Skip the jump to else when the condition holds, then jump past it
+        assert_eq!(code, [0x3005, 0x1208, 0x6101, 0x120A, 0x6102]);
+    }
+
+    #[test]
+    fn loop_while_again_breaks_out_past_again() {
+        let code = words(": main loop v0 += 1 while v0 != 10 again");
+        assert_eq!(code, [0x7001, 0x400A, 0x1208, 0x1200]);
+    }
+
+    #[test]
+    fn forward_labels_are_fixed_up() {
+        let asm = assemble(": main jump later v0 := 1 : later return").unwrap();
+        assert_eq!(asm.labels["later"], 0x204);
+        assert_eq!(asm.rom, [0x12, 0x04, 0x60, 0x01, 0x00, 0xEE]);
+        // main not first gets a jump to it at 0x200
+        assert_eq!(words(": sub return : main sub"), [0x1204, 0x00EE, 0x2202]);
+        assert_eq!(assemble(": main i := long data : data 1").unwrap().rom, [0xF0, 0x00, 0x02, 0x04, 0x01]);
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        assert_eq!(words(": main if v1 < 5 then v2 := 0"), [0x6F05, 0x8F17, 0x4F00, 0x6200]);
+        assert_eq!(words(": main if v1 > 5 then v2 := 0"), [0x6F05, 0x8F15, 0x4F00, 0x6200]);
+        assert_eq!(words(": main if v1 <= v2 then v3 := 0"), [0x8F20, 0x8F15, 0x4F01, 0x6300]);
+        assert_eq!(words(": main if v1 >= v2 then v3 := 0"), [0x8F20, 0x8F17, 0x4F01, 0x6300]);
+        // In a begin block the final test is inverted
+        assert_eq!(words(": main if v1 < 5 begin v2 := 0 end"), [0x6F05, 0x8F17, 0x3F00, 0x120A, 0x6200]);
+    }
+
+    #[test]
+    fn consts_aliases_and_macros() {
+        let source = ":const SPEED 3 :alias x v4 :macro step reg { reg += SPEED } : main step x step v5";
+        assert_eq!(words(source), [0x1202, 0x7403, 0x7503]);
+    }
+
+    #[test]
+    fn errors_point_at_the_right_line() {
+        assert_eq!(error_line(": main\n  v0 := 1\n  v0 := 300\n"), 3);
+        assert_eq!(error_line(": main\n\n  jump nowhere\n  return\n"), 3);
+        assert_eq!(error_line(": main\n  loop\n    v0 += 1\n  if v0 == 1 begin\n"), 4);
+        assert_eq!(error_line(": main\n  else\n"), 2);
+        assert_eq!(error_line(": main\n  :unpack 1 0x1234\n"), 2);
+        assert_eq!(error_line(": main\n  :unpack 1 far\n  :org 0x1200\n: far\n  return\n"), 2);
+    }
+
+    #[test]
+    fn blocks_past_0xfff_are_errors() {
+        // Right up to the limit still assembles
+        let rom = assemble(": main jump far :org 0xFF8 : far if v0 == 1 begin v1 := 2 end").unwrap().rom;
+        assert_eq!(rom[0xFF8 - PROGRAM_START..], [0x30, 0x01, 0x1F, 0xFE, 0x61, 0x02]);
+
+        let past_else = ": main jump far\n:org 0xFFA\n: far\n  if v0 == 1 begin\n    v1 := 2\n  else\n    v1 := 3\n  end\n";
+        let err = assemble(past_else).err().unwrap();
+        assert_eq!((err.line, err.message.as_str()), (6, "jump target 0x1002 is out of range"));
+        let past_end = ": main jump far\n:org 0xFFC\n: far\n  if v0 == 1 begin\n    v1 := 2\n  end\n";
+        assert_eq!(error_line(past_end), 6);
+        let past_again = ": main jump far\n:org 0xFFA\n: far\n  loop\n    while v0 != 1\n  again\n";
+        assert_eq!(error_line(past_again), 6);
+        let loop_above = ": main jump far\n:org 0x1000\n: far\n  loop\n    v0 += 1\n  again\n";
+        assert_eq!(error_line(loop_above), 6);
+    }
+
+    #[test]
+    fn disassembles_back_to_the_same_program() {
+        let source = "
+            : main
+                clear
+                v0 := 0x0C
+                v1 := 8
+                i := ball
+                loop
+                    sprite v0 v1 3
+                    draw
+                    v0 += 1
+                again
+            : draw
+                if v0 == 60 then v0 := 0
+                return
+            : ball
+                0x40 0xE0 0x40
+        ";
+        let asm = assemble(source).unwrap();
+        let dis = Disassembly::new(&asm.rom, false);
+        let listing: Vec<String> = dis.code.iter().map(|(&addr, &i)| dis.format(addr, i)).collect();
+        assert_eq!(listing, [
+            "CLS", "LD V0, 0x0C", "LD V1, 0x08", "LD I, data_216", "DRW V0, V1, 0x3", "CALL sub_210",
+            "ADD V0, 0x01", "JP label_208", "SNE V0, 0x3C", "LD V0, 0x00", "RET",
+        ]);
+        assert_eq!(dis.labels[&(asm.labels["draw"] as usize)], LabelKind::Subroutine);
+        assert_eq!(dis.labels[&(asm.labels["ball"] as usize)], LabelKind::Data);
+        // Every word before the sprite is code, and the sprite is all that's left
+        let ball = asm.labels["ball"] as usize;
+        let addrs: Vec<usize> = dis.code.keys().copied().collect();
+        assert_eq!(addrs, (PROGRAM_START..ball).step_by(2).collect::<Vec<_>>());
+        assert_eq!(&asm.rom[ball - PROGRAM_START..], [0x40, 0xE0, 0x40]);
+    }
+}
@@ -0,0 +1,51 @@
+// Assembles Octo source into a .ch8 ROM
+use std::path::Path;
+
+use rusty_chip8::asm::assemble;
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-asm <source.8o> [-o <out.ch8>] [--symbols <path>]");
+    std::process::exit(1);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut source_path = None;
+    let mut out_path = None;
+    let mut symbols_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => symbols_path = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') => usage(),
+            _ if source_path.is_none() => source_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let source_path = source_path.unwrap_or_else(|| usage());
+    // prog.8o -> prog.ch8 unless told otherwise
+    let out_path = out_path.unwrap_or_else(|| {
+        Path::new(&source_path).with_extension("ch8").to_string_lossy().into_owned()
+    });
+
+    let source = std::fs::read_to_string(&source_path)
+        .unwrap_or_else(|e| fail(format!("Could not read {}: {}", source_path, e)));
+    let assembly = assemble(&source)
+        .unwrap_or_else(|e| fail(format!("{}: {}", source_path, e)));
+
+    std::fs::write(&out_path, &assembly.rom)
+        .unwrap_or_else(|e| fail(format!("Could not write {}: {}", out_path, e)));
+    if let Some(symbols_path) = symbols_path {
+        let written = std::fs::File::create(&symbols_path)
+            .and_then(|mut f| assembly.write_symbols(&mut f));
+        if let Err(e) = written {
+            fail(format!("Could not write {}: {}", symbols_path, e));
+        }
+    }
+    println!("Wrote {} bytes to {}", assembly.rom.len(), out_path);
+}
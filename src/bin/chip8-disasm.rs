@@ -0,0 +1,35 @@
+// Disassembles a ROM to stdout, separating reachable code from data
+use rusty_chip8::disasm::Disassembly;
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-disasm [--xochip] <rom>");
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut xo_chip = false;
+    let mut rom_path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--xochip" => xo_chip = true,
+            _ if arg.starts_with("--") => usage(),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| usage());
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", rom_path, e);
+        std::process::exit(1);
+    });
+
+    let disassembly = Disassembly::new(&rom, xo_chip);
+    let stdout = std::io::stdout();
+    if let Err(e) = disassembly.write_listing(&mut stdout.lock()) {
+        // Most likely the output was piped into something like head
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Runs a ROM without a window or sound, for build servers and batch jobs.
+// Stops after a number of frames or on a halt condition, then writes the
+// screen as PNG or PBM and the registers as JSON.
+use std::fs;
+use std::io::{self, Write};
+
+use rusty_chip8::chip8::{Chip8, NUM_KEYS};
+use rusty_chip8::decoder::{self, Instruction};
+use rusty_chip8::frontend::{AudioCapture, InputProvider};
+use rusty_chip8::image;
+use rusty_chip8::palette::{self, Palette};
+use rusty_chip8::quirks::Quirks;
+use rusty_chip8::random::RngMode;
+use rusty_chip8::scheduler;
+
+const DEFAULT_FRAMES: u64 = 600;
+// Long enough for Fx0A, which waits for the key to come back up
+const DEFAULT_HOLD: u64 = 4;
+
+// A key held down from `frame` for `frames` frames
+struct Press {
+    frame: u64,
+    key: usize,
+    frames: u64,
+}
+
+// Plays the --key presses back as the keypad
+struct Script {
+    presses: Vec<Press>,
+    frame: u64,
+}
+
+impl InputProvider for Script {
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+        *keys = [false; NUM_KEYS];
+        for p in self.presses.iter() {
+            if self.frame >= p.frame && self.frame < p.frame + p.frames {
+                keys[p.key] = true;
+            }
+        }
+        self.frame += 1;
+        false
+    }
+}
+
+struct Options {
+    rom: String,
+    frames: u64,
+    ipf: u32,
+    quirks: Option<Quirks>,
+    xo_chip: bool,
+    seed: u64,
+    rng: RngMode,
+    presses: Vec<Press>,
+    stop_at: Option<usize>,
+    stop_on_loop: bool,
+    screenshot: Option<String>,
+    scale: usize,
+    palette: Palette,
+    json: Option<String>,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-headless [options] <rom>");
+    eprintln!("  --frames <n>             frames to run (default {})", DEFAULT_FRAMES);
+    eprintln!("  --ipf <n>                instructions per 60 Hz frame");
+    eprintln!("  --quirks <{}>", Quirks::PRESET_NAMES.join("|"));
+    eprintln!("  --xochip                 enable XO-CHIP extensions");
+    eprintln!("  --seed <n>               seed for Cxkk's random numbers (default 0)");
+    eprintln!("  --rng <{}>", RngMode::NAMES.join("|"));
+    eprintln!("  --key <frame>:<key>[:<frames>]");
+    eprintln!("                           hold a key (hex) from a frame, {} frames by default", DEFAULT_HOLD);
+    eprintln!("  --stop-at <addr>         stop when the PC reaches a (hex) address");
+    eprintln!("  --stop-on-loop           stop on a jump to itself");
+    eprintln!("  --screenshot <path>      write the screen, .pbm or .png");
+    eprintln!("  --scale <n>              PNG pixels per CHIP-8 pixel");
+    eprintln!("  --palette <{}>", Palette::NAMES.join("|"));
+    eprintln!("  --fg <RRGGBB>            custom foreground colour");
+    eprintln!("  --bg <RRGGBB>            custom background colour");
+    eprintln!("  --json <path>            write the registers here instead of stdout");
+    std::process::exit(1);
+}
+
+fn parse_press(spec: &str) -> Option<Press> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (frame, key, frames) = match parts[..] {
+        [frame, key] => (frame, key, None),
+        [frame, key, frames] => (frame, key, Some(frames)),
+        _ => return None,
+    };
+    let key = usize::from_str_radix(key, 16).ok().filter(|&k| k < NUM_KEYS)?;
+    let frames = match frames {
+        Some(n) => n.parse().ok()?,
+        None => DEFAULT_HOLD,
+    };
+    Some(Press { frame: frame.parse().ok()?, key, frames })
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        rom: String::new(),
+        frames: DEFAULT_FRAMES,
+        ipf: scheduler::DEFAULT_IPF,
+        quirks: None,
+        xo_chip: false,
+        seed: 0,
+        rng: RngMode::default(),
+        presses: Vec::new(),
+        stop_at: None,
+        stop_on_loop: false,
+        screenshot: None,
+        scale: 1,
+        palette: Palette::default(),
+        json: None,
+    };
+    let mut rom = None;
+    let (mut fg, mut bg) = (None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => opts.frames = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+            "--ipf" => opts.ipf = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or_else(|| usage()),
+            "--quirks" => opts.quirks = Some(args.next().and_then(|q| Quirks::from_name(&q)).unwrap_or_else(|| usage())),
+            "--xochip" => opts.xo_chip = true,
+            "--seed" => opts.seed = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+            "--rng" => opts.rng = args.next().and_then(|m| RngMode::from_name(&m)).unwrap_or_else(|| usage()),
+            "--key" => opts.presses.push(args.next().and_then(|k| parse_press(&k)).unwrap_or_else(|| usage())),
+            "--stop-at" => {
+                opts.stop_at = Some(args.next().and_then(|a| usize::from_str_radix(a.trim_start_matches("0x"), 16).ok())
+                    .unwrap_or_else(|| usage()));
+            }
+            "--stop-on-loop" => opts.stop_on_loop = true,
+            "--screenshot" => opts.screenshot = Some(args.next().unwrap_or_else(|| usage())),
+            "--scale" => opts.scale = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+            "--palette" => opts.palette = args.next().and_then(|p| Palette::from_name(&p)).unwrap_or_else(|| usage()),
+            "--fg" => fg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--bg" => bg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--json" => opts.json = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--") => usage(),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => usage(),
+        }
+    }
+    opts.rom = rom.unwrap_or_else(|| usage());
+    opts.palette = opts.palette.recolor(fg, bg);
+    opts
+}
+
+// Why the run ended
+enum Stop {
+    Frames,
+    Exit,
+    Address,
+    Loop,
+    Fault(String),
+}
+
+impl Stop {
+    fn name(&self) -> &'static str {
+        match self {
+            Stop::Frames => "frames",
+            Stop::Exit => "exit",
+            Stop::Address => "address",
+            Stop::Loop => "loop",
+            Stop::Fault(_) => "fault",
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn write_json(out: &mut dyn Write, c8: &Chip8, frames: u64, stop: &Stop, beep_frames: usize) -> io::Result<()> {
+    let v: Vec<String> = c8.v().iter().map(|v| v.to_string()).collect();
+    let stack: Vec<String> = c8.stack().iter().map(|addr| addr.to_string()).collect();
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"frames\": {},", frames)?;
+    writeln!(out, "  \"stop\": {},", json_string(stop.name()))?;
+    if let Stop::Fault(message) = stop {
+        writeln!(out, "  \"error\": {},", json_string(message))?;
+    }
+    writeln!(out, "  \"pc\": {},", c8.pc())?;
+    writeln!(out, "  \"i\": {},", c8.i())?;
+    writeln!(out, "  \"v\": [{}],", v.join(", "))?;
+    writeln!(out, "  \"dt\": {},", c8.dt())?;
+    writeln!(out, "  \"st\": {},", c8.st())?;
+    writeln!(out, "  \"sp\": {},", c8.stack().len())?;
+    writeln!(out, "  \"stack\": [{}],", stack.join(", "))?;
+    writeln!(out, "  \"width\": {},", c8.disp_x())?;
+    writeln!(out, "  \"height\": {},", c8.disp_y())?;
+    writeln!(out, "  \"beep_frames\": {}", beep_frames)?;
+    writeln!(out, "}}")
+}
+
+fn run(c8: &mut Chip8, opts: &Options, input: &mut Script, audio: &mut AudioCapture) -> (u64, Stop) {
+    for frame in 0..opts.frames {
+        input.poll_keys(&mut c8.keys);
+        for _ in 0..opts.ipf {
+            let pc = c8.pc();
+            if opts.stop_at == Some(pc) {
+                return (frame, Stop::Address);
+            }
+            let jumps_to_self = c8.mem().get(pc..pc + 2)
+                .and_then(|b| decoder::decode(u16::from_be_bytes([b[0], b[1]])).ok())
+                == Some(Instruction::Jp(pc as u16));
+            if opts.stop_on_loop && jumps_to_self {
+                return (frame, Stop::Loop);
+            }
+            if let Err(e) = c8.cycle() {
+                return (frame, Stop::Fault(e.to_string()));
+            }
+            if c8.exit_flag {
+                break;
+            }
+        }
+        c8.end_frame(audio);
+        if c8.exit_flag {
+            return (frame + 1, Stop::Exit);
+        }
+    }
+    (opts.frames, Stop::Frames)
+}
+
+fn main() {
+    let mut opts = parse_args();
+    let quirks = opts.quirks.unwrap_or(if opts.xo_chip { Quirks::XO_CHIP } else { Quirks::default() });
+    let mut c8 = Chip8::with_quirks(quirks);
+    c8.set_xo_chip(opts.xo_chip);
+    c8.rng = opts.rng.source(opts.seed);
+    c8.load_font();
+    let rom = fs::read(&opts.rom).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", opts.rom, e);
+        std::process::exit(1);
+    });
+    if let Err(e) = c8.load_rom_bytes(&rom) {
+        eprintln!("Could not load {}: {}", opts.rom, e);
+        std::process::exit(1);
+    }
+
+    let mut input = Script { presses: std::mem::take(&mut opts.presses), frame: 0 };
+    let mut audio = AudioCapture::default();
+    let (frames, stop) = run(&mut c8, &opts, &mut input, &mut audio);
+    let beep_frames = audio.ticks.iter().filter(|&&on| on).count();
+
+    if let Some(path) = &opts.screenshot {
+        let (gfx, width, height) = (c8.screen(), c8.disp_x(), c8.disp_y());
+        let result = fs::File::create(path).map(io::BufWriter::new).and_then(|mut file| {
+            if path.to_lowercase().ends_with(".pbm") {
+                image::write_pbm(&mut file, gfx, width, height)?;
+            } else {
+                image::write_png(&mut file, gfx, width, height, &opts.palette.colors, opts.scale)?;
+            }
+            file.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("Could not write {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    let result = match &opts.json {
+        Some(path) => fs::File::create(path).and_then(|mut file| write_json(&mut file, &c8, frames, &stop, beep_frames)),
+        None => write_json(&mut io::stdout().lock(), &c8, frames, &stop, beep_frames),
+    };
+    if let Err(e) = result {
+        eprintln!("Could not write the register dump: {}", e);
+        std::process::exit(1);
+    }
+    // Let scripts tell a crashed ROM from a finished run
+    if let Stop::Fault(message) = &stop {
+        eprintln!("Halted: {}", message);
+        std::process::exit(2);
+    }
+}
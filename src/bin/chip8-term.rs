@@ -0,0 +1,132 @@
+// Plays a ROM in the terminal, for when there is no display (e.g. over SSH)
+use rusty_chip8::chip8::Chip8;
+use rusty_chip8::dir;
+use rusty_chip8::frontend::{FrameSink, InputProvider};
+use rusty_chip8::palette::{self, Palette};
+use rusty_chip8::quirks::Quirks;
+use rusty_chip8::random::RngMode;
+use rusty_chip8::scheduler::{self, Scheduler};
+use rusty_chip8::terminal::{Bell, RenderMode, Terminal};
+
+const GAMES_DIR: &str = "./GAMES";
+
+struct Options {
+    rom: Option<String>,
+    ipf: u32,
+    quirks: Option<Quirks>,
+    xo_chip: bool,
+    seed: Option<u64>,
+    rng: RngMode,
+    render: RenderMode,
+    bell: bool,
+    palette: Palette,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: chip8-term [options] [rom]");
+    eprintln!("  --render <{}>", RenderMode::NAMES.join("|"));
+    eprintln!("  --bell                   ring the terminal bell for the buzzer");
+    eprintln!("  --palette <{}>", Palette::NAMES.join("|"));
+    eprintln!("  --fg <RRGGBB>            custom foreground colour");
+    eprintln!("  --bg <RRGGBB>            custom background colour");
+    eprintln!("  --ipf <n>                instructions per 60 Hz frame");
+    eprintln!("  --quirks <{}>", Quirks::PRESET_NAMES.join("|"));
+    eprintln!("  --xochip                 enable XO-CHIP extensions");
+    eprintln!("  --seed <n>               seed for Cxkk's random numbers");
+    eprintln!("  --rng <{}>", RngMode::NAMES.join("|"));
+    eprintln!("Without a ROM, one is picked from {}", GAMES_DIR);
+    std::process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        rom: None,
+        ipf: scheduler::DEFAULT_IPF,
+        quirks: None,
+        xo_chip: false,
+        seed: None,
+        rng: RngMode::default(),
+        render: RenderMode::default(),
+        bell: false,
+        palette: Palette::default(),
+    };
+    let (mut fg, mut bg) = (None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--render" => opts.render = args.next().and_then(|m| RenderMode::from_name(&m)).unwrap_or_else(|| usage()),
+            "--bell" => opts.bell = true,
+            "--palette" => opts.palette = args.next().and_then(|p| Palette::from_name(&p)).unwrap_or_else(|| usage()),
+            "--fg" => fg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--bg" => bg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--ipf" => opts.ipf = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or_else(|| usage()),
+            "--quirks" => opts.quirks = Some(args.next().and_then(|q| Quirks::from_name(&q)).unwrap_or_else(|| usage())),
+            "--xochip" => opts.xo_chip = true,
+            "--seed" => opts.seed = Some(args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage())),
+            "--rng" => opts.rng = args.next().and_then(|m| RngMode::from_name(&m)).unwrap_or_else(|| usage()),
+            _ if arg.starts_with("--") => usage(),
+            _ if opts.rom.is_none() => opts.rom = Some(arg),
+            _ => usage(),
+        }
+    }
+    opts.palette = opts.palette.recolor(fg, bg);
+    opts
+}
+
+fn main() {
+    let opts = parse_args();
+    // Picking a game is done before the terminal goes raw
+    let rom = opts.rom.clone().unwrap_or_else(|| dir::Navigator::select_game(GAMES_DIR));
+    let quirks = opts.quirks.unwrap_or(if opts.xo_chip { Quirks::XO_CHIP } else { Quirks::default() });
+    let mut c8 = Chip8::with_quirks(quirks);
+    c8.set_xo_chip(opts.xo_chip);
+    let seed = opts.seed.unwrap_or_else(rand::random);
+    c8.rng = opts.rng.source(seed);
+    c8.load_font();
+    if let Err(e) = c8.load_rom(rom.clone()) {
+        eprintln!("Could not load {}: {}", rom, e);
+        std::process::exit(1);
+    }
+
+    let mut term = Terminal::new(opts.render).unwrap_or_else(|e| {
+        eprintln!("Could not set up the terminal: {}", e);
+        std::process::exit(1);
+    });
+    term.palette = opts.palette;
+    let mut bell = Bell::new(opts.bell);
+    let mut scheduler = Scheduler::new();
+    let mut paused = false;
+    let error = 'running: loop {
+        if paused {
+            if term.poll_keys(&mut c8.keys) {
+                break None;
+            }
+        } else {
+            for _ in 0..scheduler.frames_due() {
+                match c8.run_frame(opts.ipf, &mut term, &mut bell) {
+                    Ok(true) => break 'running None,
+                    Ok(false) if c8.exit_flag => break 'running None,
+                    Ok(false) => {}
+                    Err(e) => break 'running Some(e),
+                }
+            }
+        }
+        if term.take_pause() {
+            paused = !paused;
+            scheduler.reset();
+            let _ = term.status(if paused { "Paused, P to resume" } else { "" });
+        }
+        if c8.draw_flag || term.needs_redraw() {
+            c8.draw_flag = false;
+            term.present(c8.screen(), c8.disp_x(), c8.disp_y());
+        }
+        scheduler.wait();
+    };
+    // Put the terminal back before printing anything
+    drop(term);
+    if let Some(e) = error {
+        eprintln!("Halted: {}", e);
+        c8.print_registers();
+        std::process::exit(1);
+    }
+}
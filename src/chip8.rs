@@ -1,25 +1,122 @@
 pub const PIXEL_SIZE: usize = 20;
+// Low resolution (CHIP-8) display
 pub const DISP_X: usize = 64;
 pub const DISP_Y: usize = 32;
+// High resolution (SUPER-CHIP) display
+pub const HIRES_X: usize = 128;
+pub const HIRES_Y: usize = 64;
+// Two page "HIRES CHIP-8" display used by the ROMs in GAMES/hires
+pub const TALL_Y: usize = 64;
 pub const NUM_VREGS: usize = 16;
+pub const NUM_KEYS: usize = 16;
+// Nesting depth of subroutine calls (SUPER-CHIP allows 16)
+pub const STACK_SIZE: usize = 16;
+// Programs are loaded and start at 0x200
+pub const PROGRAM_START: usize = 0x200;
+// SUPER-CHIP "RPL user flags" saved/restored by Fx75/Fx85
+pub const NUM_RPL: usize = 8;
 const MEM_SIZE: usize = 4096;
+// XO-CHIP address space
+const XO_MEM_SIZE: usize = 0x10000;
+// XO-CHIP bitplanes. Each gfx byte holds one bit per plane, which gives four
+// colours: 0 = off, 1 = plane 1, 2 = plane 2, 3 = both.
+pub const NUM_PLANES: usize = 2;
+const FONT_ADDR: usize = 0x0;
+const BIG_FONT_ADDR: usize = 0x50;
 const MSB_POS: usize = 7;
-const NUM_KEYS: usize = 16;
-const PX: &'static str = "\u{2588}\u{2588}";
-use crate::input;
+const PX: &str = "\u{2588}\u{2588}";
+use crate::frontend::{AudioSink, InputProvider};
+use crate::decoder::{decode, Instruction};
+use crate::error::{Chip8Error, ErrorPolicy};
+use crate::quirks::{LoadStoreI, Quirks};
+use crate::random::{RandomSource, RngMode, Uniform};
+use crate::savestate::{self, StateError, StateReader, StateWriter};
+use crate::trace::{TraceState, Tracer};
 use std::num::Wrapping;
 use std::vec::Vec;
-use rand::Rng;
+
+const FONT: [u8; 80] = [
+    // 1,    2, 	3, 	  4, 	5 bytes
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+// SUPER-CHIP 8x10 font used by Fx30. The original only has 0-9, A-F are the
+// glyphs Octo added so that every hex digit has a big form.
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    // 64x32, plain CHIP-8
+    Low,
+    // 64x64, HIRES CHIP-8 (1977). Programs start with a 1260 jump.
+    Tall,
+    // 128x64, SUPER-CHIP
+    High,
+}
+
+// Where pc goes once an instruction is done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    // On to the next instruction
+    Next,
+    // The instruction already set it: jumps, calls, and Fx0A repeating
+    // itself while it waits for a key
+    Jumped,
+}
 
 #[derive(Debug)]
 pub struct Chip8 {
-    mem: [u8; MEM_SIZE],
+    mem: Vec<u8>,
     // gfx can technically be a boolean array but I prefer using u8 
-    // so that I can cleanly XOR its values
-    pub gfx: [u8; DISP_X * DISP_Y],
+    // so that I can cleanly XOR its values.
+    // It is always big enough for the high resolution mode, but only the
+    // first disp_x() * disp_y() pixels are in use (row-major, disp_x() wide).
+    pub gfx: [u8; HIRES_X * HIRES_Y],
+    pub resolution: Resolution,
+    // Bitmask of the planes that drawing, clearing and scrolling act on (Fn01)
+    plane: u8,
     pub keys: [bool; NUM_KEYS],
     pub draw_flag: bool,
     pub exit_flag: bool,
+    // Key that Fx0A saw go down and is waiting to see released
+    key_wait: Option<u8>,
+    pub quirks: Quirks,
+    pub error_policy: ErrorPolicy,
+    pub tracer: Tracer,
+    // XO-CHIP extensions (64 KiB memory, long I, bitplanes, ...)
+    pub xo_chip: bool,
 
     stack: Vec<u16>,
     // Registers
@@ -29,12 +126,29 @@ pub struct Chip8 {
     dt: u8,
     st: u8,
     opcode: u16,
+    rpl: [u8; NUM_VREGS],
+    // Identifies the loaded ROM in save states
+    rom_hash: u64,
+    // Source of Cxkk's random numbers. Seeded from the OS unless told
+    // otherwise, replace it or reseed it for reproducible runs.
+    pub rng: Box<dyn RandomSource>,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Self {
-        let mem: [u8; MEM_SIZE] = [0; MEM_SIZE];
-        let gfx: [u8; DISP_X * DISP_Y] = [0; DISP_X * DISP_Y];
+        Self::with_quirks(Quirks::default())
+    }
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mem: Vec<u8> = vec![0; MEM_SIZE];
+        let gfx: [u8; HIRES_X * HIRES_Y] = [0; HIRES_X * HIRES_Y];
+        let resolution = Resolution::Low;
+        let plane: u8 = 0x1;
         let keys: [bool; NUM_KEYS] = [false; NUM_KEYS];
         let draw_flag: bool = false;
         let stack: Vec<u16> = Vec::new();
@@ -42,452 +156,824 @@ impl Chip8 {
         // TODO: Add stack and clock
         let v: [u8; NUM_VREGS] = [0; NUM_VREGS];
         let i: u16 = 0x0;
-        let pc: usize = 0x200;
+        let pc: usize = PROGRAM_START;
         let dt: u8 = 0x0;
         let st: u8 = 0x0;
         let opcode: u16 = 0x0;
+        // SUPER-CHIP only has 8 flags but XO-CHIP allows all 16
+        let rpl: [u8; NUM_VREGS] = [0; NUM_VREGS];
 
         let exit_flag = false;
-        Self { mem, gfx, keys, draw_flag, stack, 
+        let key_wait = None;
+        let xo_chip = false;
+        Self { mem, gfx, resolution, plane, keys, draw_flag, stack, 
                v, i, pc, dt, st, 
-               opcode,
-               exit_flag }
+               opcode, rpl, rom_hash: 0,
+               rng: Box::new(Uniform::new(rand::random())),
+               exit_flag, key_wait, quirks, xo_chip,
+               error_policy: ErrorPolicy::default(),
+               tracer: Tracer::default() }
+    }
+    // Turns the XO-CHIP extensions on or off. Must be called before loading
+    // the font and ROM, since it resizes memory.
+    pub fn set_xo_chip(&mut self, enabled: bool){
+        self.xo_chip = enabled;
+        self.mem = vec![0; if enabled { XO_MEM_SIZE } else { MEM_SIZE }];
     }
     // Load all font data to chip8 memory
-    pub fn load_font(self: &mut Self){
-        let textfont: [u8; 80] = [
-            // 1,    2, 	3, 	  4, 	5 bytes
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-        ];
-        self.mem[0..80].clone_from_slice(&textfont);
-    }
-
-    // Load rom into memory starting at address 0x200
-    pub fn load_rom(self: &mut Self, rom_path: String){
-        println!("Loading game: {}", rom_path);
-        let mut _file = std::fs::File::open(&rom_path)
-            .expect("No file found");        
-        // get file metadata
-        let metadata = std::fs::metadata(&rom_path).unwrap();
+    pub fn load_font(&mut self){
+        self.mem[FONT_ADDR..FONT_ADDR + FONT.len()].clone_from_slice(&FONT);
+        self.mem[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT.len()].clone_from_slice(&BIG_FONT);
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+    // Width of the display in the current resolution
+    pub fn disp_x(&self) -> usize {
+        match self.resolution {
+            Resolution::Low | Resolution::Tall => DISP_X,
+            Resolution::High => HIRES_X,
+        }
+    }
+    // Height of the display in the current resolution
+    pub fn disp_y(&self) -> usize {
+        match self.resolution {
+            Resolution::Low => DISP_Y,
+            Resolution::Tall => TALL_Y,
+            Resolution::High => HIRES_Y,
+        }
+    }
+    // The part of gfx that is actually on screen
+    pub fn screen(&self) -> &[u8] {
+        &self.gfx[0..self.disp_x() * self.disp_y()]
+    }
+
+    // Switches display resolution, clearing the screen
+    fn set_resolution(&mut self, resolution: Resolution){
+        self.resolution = resolution;
+        self.gfx.fill(0);
+        self.draw_flag = true;
+    }
+
+    // Clears the selected planes
+    fn clear_planes(&mut self){
+        let keep = !self.plane;
+        for px in self.gfx.iter_mut() {
+            *px &= keep;
+        }
+        self.draw_flag = true;
+    }
+
+    // Scrolls the selected planes by (dx, dy) pixels, filling with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize){
+        let (w, h) = (self.disp_x() as isize, self.disp_y() as isize);
+        let old = self.gfx;
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = (x - dx, y - dy);
+                let src = if sx >= 0 && sx < w && sy >= 0 && sy < h {
+                    old[(sy * w + sx) as usize]
+                } else {
+                    0
+                };
+                let dest = (y * w + x) as usize;
+                self.gfx[dest] = (old[dest] & !self.plane) | (src & self.plane);
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // Skips the next instruction. The XO-CHIP long I load is twice as wide
+    // as everything else, so it has to be stepped over as a whole.
+    fn skip(&mut self){
+        let next = self.pc + 2;
+        if self.xo_chip && self.mem.get(next..next + 2) == Some(&[0xF0, 0x00]) {
+            self.pc += 4;
+        } else {
+            self.pc += 2;
+        }
+    }
+
+    // Load rom into memory starting at address 0x200. Returns its size.
+    pub fn load_rom(&mut self, rom_path: String) -> Result<usize, Chip8Error> {
         // copy raw file data to memory (fs::read conveniently returns a u8 vector)
-        let mut _rom_data = std::fs::read(&rom_path).unwrap();
+        let rom_data = std::fs::read(&rom_path)?;
+        self.load_rom_bytes(&rom_data)?;
+        Ok(rom_data.len())
+    }
+
+    // Load a ROM image that is already in memory
+    pub fn load_rom_bytes(&mut self, rom_data: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.mem.len() - PROGRAM_START;
+        if rom_data.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom_data.len(), max });
+        }
+        self.mem[PROGRAM_START..PROGRAM_START + rom_data.len()]
+            .clone_from_slice(rom_data);
+        self.rom_hash = savestate::rom_hash(rom_data);
+
+        // HIRES CHIP-8 programs begin with a jump over the 1802 machine code
+        // that patched the VIP interpreter for a 64x64 display. We provide
+        // that display ourselves, so skip straight to the CHIP-8 code.
+        // Frontends can tell from resolution().
+        if rom_data.starts_with(&[0x12, 0x60]) {
+            self.set_resolution(Resolution::Tall);
+            self.pc = 0x2C0;
+        }
+        Ok(())
+    }
+
+    // Makes Cxkk produce the same sequence on every run
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // Registers, stack and memory for debuggers and other tools. The
+    // setters don't check anything, it's the caller's machine to break.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+    pub fn v(&self) -> &[u8; NUM_VREGS] {
+        &self.v
+    }
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+    pub fn set_dt(&mut self, dt: u8) {
+        self.dt = dt;
+    }
+    pub fn set_st(&mut self, st: u8) {
+        self.st = st;
+    }
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+    pub fn mem_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
+    // Snapshot of the whole machine, see savestate.rs for the layout
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::default();
+        w.bytes(&savestate::MAGIC);
+        w.u16(savestate::VERSION);
+        w.u64(self.rom_hash);
+        w.u8(self.xo_chip as u8);
+        w.u16(self.pc as u16);
+        w.u16(self.i);
+        w.u16(self.opcode);
+        w.u8(self.dt);
+        w.u8(self.st);
+        w.u8(self.plane);
+        w.u8(self.resolution as u8);
+        w.u8(self.key_wait.unwrap_or(0xFF));
+        w.u8(self.exit_flag as u8);
+        w.u64(self.rng.state());
+        w.u8(match self.rng.mode() {
+            Some(RngMode::Uniform) => 0,
+            Some(RngMode::Vip) => 1,
+            None => 0xFF,
+        });
+        w.bytes(&self.v);
+        w.bytes(&self.rpl);
+        for &key in self.keys.iter() {
+            w.u8(key as u8);
+        }
+        w.u8(self.stack.len() as u8);
+        for &addr in self.stack.iter() {
+            w.u16(addr);
+        }
+        w.u32(self.mem.len() as u32);
+        w.bytes(&self.mem);
+        w.u32(self.gfx.len() as u32);
+        w.bytes(&self.gfx);
+        w.buf
+    }
 
-        self.mem[0x200..0x200+_rom_data.len()]
-            .clone_from_slice(&_rom_data);
+    // Restores a snapshot taken by save_state. Nothing is changed unless
+    // the whole state is valid and was saved while running the same ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        if r.bytes(savestate::MAGIC.len())? != savestate::MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if !(savestate::MIN_VERSION..=savestate::VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let found = r.u64()?;
+        if found != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found });
+        }
+        let xo_chip = r.u8()? != 0;
+        let pc = r.u16()? as usize;
+        let i = r.u16()?;
+        let opcode = r.u16()?;
+        let dt = r.u8()?;
+        let st = r.u8()?;
+        let plane = r.u8()?;
+        let resolution = match r.u8()? {
+            0 => Resolution::Low,
+            1 => Resolution::Tall,
+            2 => Resolution::High,
+            _ => return Err(StateError::Corrupt("resolution")),
+        };
+        let key_wait = match r.u8()? {
+            0xFF => None,
+            key if (key as usize) < NUM_KEYS => Some(key),
+            _ => return Err(StateError::Corrupt("key wait")),
+        };
+        let exit_flag = r.u8()? != 0;
+        let rng_state = if version >= 2 { Some(r.u64()?) } else { None };
+        let rng_mode = match if version >= 3 { r.u8()? } else { 0xFF } {
+            0 => Some(RngMode::Uniform),
+            1 => Some(RngMode::Vip),
+            0xFF => None,
+            _ => return Err(StateError::Corrupt("RNG mode")),
+        };
+        let mut v = [0; NUM_VREGS];
+        v.copy_from_slice(r.bytes(NUM_VREGS)?);
+        let mut rpl = [0; NUM_VREGS];
+        rpl.copy_from_slice(r.bytes(NUM_VREGS)?);
+        let mut keys = [false; NUM_KEYS];
+        for (key, &b) in keys.iter_mut().zip(r.bytes(NUM_KEYS)?) {
+            *key = b != 0;
+        }
+        let depth = r.u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(StateError::Corrupt("stack depth"));
+        }
+        let mut stack = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            stack.push(r.u16()?);
+        }
+        let mem_len = r.u32()? as usize;
+        if mem_len != if xo_chip { XO_MEM_SIZE } else { MEM_SIZE } || pc >= mem_len {
+            return Err(StateError::Corrupt("memory size"));
+        }
+        let mem = r.bytes(mem_len)?.to_vec();
+        let gfx_len = self.gfx.len();
+        if r.u32()? as usize != gfx_len {
+            return Err(StateError::Corrupt("display size"));
+        }
+        let gfx = r.bytes(gfx_len)?;
+        self.gfx.copy_from_slice(gfx);
 
-        let _filesize = metadata.len();
-        println!("{} bits loaded into memory", _filesize);
-        // close file
-        drop(_file);
+        self.xo_chip = xo_chip;
+        self.pc = pc;
+        self.i = i;
+        self.opcode = opcode;
+        self.dt = dt;
+        self.st = st;
+        self.plane = plane;
+        self.resolution = resolution;
+        self.key_wait = key_wait;
+        self.exit_flag = exit_flag;
+        if let Some(state) = rng_state {
+            match rng_mode {
+                Some(mode) if self.rng.mode() != Some(mode) => self.rng = mode.source(state),
+                _ => self.rng.seed(state),
+            }
+        }
+        self.v = v;
+        self.rpl = rpl;
+        self.keys = keys;
+        self.stack = stack;
+        self.mem = mem;
+        self.draw_flag = true;
+        Ok(())
     }
 
-    // delay timer
-    pub fn count_dt(self: &mut Self){
-        while self.dt > 0 {
-            // println!("Counting down");
+    // delay timer, counts down once per 60 Hz tick
+    pub fn count_dt(&mut self){
+        if self.dt > 0 {
             self.dt -= 1;
-            std::thread::sleep(std::time::Duration::new(0, 100_000_000u32 / 60));
         }
-        // Also delay for every frame
-        std::thread::sleep(std::time::Duration::new(0, 100_000_000u32 / 60));
     }
-    // TODO: sound timer, after I actually do implement a sound to play
-    pub fn count_st(self: &mut Self){
+    // sound timer, counts down once per 60 Hz tick. The buzzer sounds for
+    // as long as it is non-zero.
+    pub fn count_st(&mut self, audio: &mut dyn AudioSink){
+        audio.set_beeping(self.st > 0);
+        if self.st > 0 {
+            self.st -= 1;
+        }
+    }
+
+    // Runs one 60 Hz frame: reads the keypad, executes `ipf` instructions and
+    // then ticks both timers once, so timing does not depend on clock speed.
+    // Returns true if the frontend wants to exit. A fault stops the frame
+    // early with the PC left on the faulting instruction.
+    pub fn run_frame(&mut self, ipf: u32, input: &mut dyn InputProvider, audio: &mut dyn AudioSink)
+        -> Result<bool, Chip8Error> {
+        if input.poll_keys(&mut self.keys) {
+            return Ok(true);
+        }
+        for _ in 0..ipf {
+            self.cycle()?;
+            if self.exit_flag {
+                break;
+            }
+        }
+        self.end_frame(audio);
+        Ok(false)
+    }
 
+    // The 60 Hz part of a frame, for callers that run the instructions
+    // themselves (see debugger.rs)
+    pub fn end_frame(&mut self, audio: &mut dyn AudioSink) {
+        self.count_dt();
+        self.count_st(audio);
+        self.rng.tick();
     }
 
-    // Execute a cpu cycle
-    pub fn cycle(self: &mut Self, input_handler: &input::Handler, sdl_context: &sdl2::Sdl){
-        self.fetch();
-        self.execute(input_handler, sdl_context);
-        self.pc += 2;
+    // Execute a cpu cycle. Faults are returned unless the error policy says
+    // to ignore them, in which case the instruction is skipped.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.pc;
+        match self.fetch().and_then(|_| self.execute()) {
+            Ok((instruction, flow)) => {
+                if self.tracer.enabled() {
+                    self.trace(pc, instruction);
+                }
+                if flow == Flow::Next {
+                    self.pc += 2;
+                }
+                Ok(())
+            }
+            Err(e) if self.error_policy == ErrorPolicy::Ignore => {
+                self.tracer.ignored(&e);
+                self.pc += 2;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
     }
     // Fetches opcode from data addressed by the program counter
-    fn fetch(self: &mut Self){
+    fn fetch(&mut self) -> Result<(), Chip8Error> {
+        self.check_mem(self.pc, 2)?;
         self.opcode = ((self.mem[self.pc] as u16) << 8) | (self.mem[self.pc + 1]) as u16;
-        println!("{:#X}: ({:#X})", self.pc, self.opcode);
+        Ok(())
+    }
+
+    fn trace(&mut self, pc: usize, instruction: Instruction){
+        let mut mnemonic = instruction.to_string();
+        if instruction == Instruction::LdILong {
+            mnemonic = format!("LD I, LONG {:#06X}", self.i);
+        }
+        let state = TraceState {
+            v: &self.v,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            sp: self.stack.len(),
+        };
+        self.tracer.instruction(pc, self.opcode, &mnemonic, state);
+    }
+
+    // Makes sure `len` bytes starting at `addr` are inside memory
+    fn check_mem(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len > self.mem.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { pc: self.pc, addr: addr + len - 1 });
+        }
+        Ok(())
+    }
+
+    fn invalid_opcode(&self) -> Chip8Error {
+        Chip8Error::InvalidOpcode { pc: self.pc, opcode: self.opcode }
     }
     
-    // Decodes and executes opcode instructions
-    fn execute(self: &mut Self, input_handler: &input::Handler, sdl_context: &sdl2::Sdl) {
-        // Decode variables from opcode 
-        let x = ((self.opcode & 0x0F00) >> 8) as usize;        
-        let y = ((self.opcode & 0x00F0) >> 4) as usize;        
-        let kk = (self.opcode & 0x00FF) as u8;
-        let nnn = (self.opcode & 0x0FFF) as u16;
-        let n = (self.opcode & 0x000F) as u8;
-
-        let mut opstr = "";
-        // Decode and execute the instruction
-        match self.opcode & 0xF000 {
-            0x0000 => {
-                match self.opcode & 0x000F {
-                    // 00E0: CLS - Clear screen
-                    0x0000 => {
-                        opstr = "CLS";
-                        self.gfx[0..DISP_X * DISP_Y].fill(0);
-                        self.draw_flag = true;
-                        println!("{}", opstr);
-                    }
-                    // 00EE: RET - Return from subroutine
-                    0x000E => {
-                        opstr = "RET";
-                        self.pc = self.stack.last().unwrap().clone() as usize;
-                        self.stack.pop();
-                        self.draw_flag = true;
-                        println!("{}", opstr);
-                    }
-                    _ => {
-                        eprintln!("Invalid 0x0000 opcode ({:#06X})", self.opcode);
-                    }
-                }
+    // Decodes and executes opcode instructions, returning what was executed
+    // and whether it already moved pc itself
+    fn execute(&mut self) -> Result<(Instruction, Flow), Chip8Error> {
+        let instruction = decode(self.opcode).map_err(|_| self.invalid_opcode())?;
+        if instruction.is_xo_chip() && !self.xo_chip {
+            return Err(self.invalid_opcode());
+        }
+        // Plain CHIP-8 has a machine code routine (SYS 230) there
+        let instruction = match instruction {
+            instruction if instruction.is_hires() && self.resolution != Resolution::Tall =>
+                Instruction::Sys(self.opcode & 0x0FFF),
+            instruction => instruction,
+        };
+
+        let mut flow = Flow::Next;
+        match instruction {
+            // 0nnn: SYS nnn - Machine code routine on the original machine's
+            // CPU. There is no 1802 here, so like modern interpreters we
+            // skip it.
+            Instruction::Sys(_) => {}
+            // 00Cn: SCD n - Scroll display down n lines (SUPER-CHIP)
+            Instruction::Scd(n) => {
+                self.scroll(0, n as isize);
+            }
+            // 00Dn: SCU n - Scroll display up n lines (XO-CHIP)
+            Instruction::Scu(n) => {
+                self.scroll(0, -(n as isize));
+            }
+            // 00E0: CLS - Clear screen
+            // 0230: CLS - Clear the 64x64 screen (HIRES CHIP-8)
+            Instruction::Cls | Instruction::ClsHires => {
+                self.clear_planes();
+            }
+            // 00EE: RET - Return from subroutine
+            Instruction::Ret => {
+                self.pc = self.stack.pop()
+                    .ok_or(Chip8Error::StackUnderflow { pc: self.pc })? as usize;
+                self.draw_flag = true;
+            }
+            // 00FB: SCR - Scroll display right 4 pixels (SUPER-CHIP)
+            Instruction::Scr => {
+                self.scroll(4, 0);
+            }
+            // 00FC: SCL - Scroll display left 4 pixels (SUPER-CHIP)
+            Instruction::Scl => {
+                self.scroll(-4, 0);
+            }
+            // 00FD: EXIT - Exit the interpreter (SUPER-CHIP)
+            Instruction::Exit => {
+                self.exit_flag = true;
+            }
+            // 00FE: LOW - Disable high resolution mode (SUPER-CHIP)
+            Instruction::Low => {
+                self.set_resolution(Resolution::Low);
+            }
+            // 00FF: HIGH - Enable 128x64 high resolution mode (SUPER-CHIP)
+            Instruction::High => {
+                self.set_resolution(Resolution::High);
             }
             // 1nnn: JP - Jump to nnn
-            0x1000 => {
-                opstr = "JP";
+            Instruction::Jp(nnn) => {
                 self.pc = nnn as usize;
-                self.pc -= 2;
-                println!("{} {:#06X}", opstr, nnn)
+                flow = Flow::Jumped;
             }
-            
             // 2nnn: CALL addr
-            0x2000 => {
-                opstr = "CALL";
+            Instruction::Call(nnn) => {
                 // Call subroutine at nnn
+                if self.stack.len() >= STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow { pc: self.pc });
+                }
                 self.stack.push(self.pc as u16);
                 // Jump to nnn
                 self.pc = nnn as usize;
-                self.pc -= 2;
-                println!("{} {:#06X}", opstr, nnn)
+                flow = Flow::Jumped;
             }
             // 3xkk: SE - Skip instruction if if Vx == kk
-            0x3000 => {
-                opstr = "SE"; 
-                if self.v[x] == kk {
-                    self.pc += 2;
-                    println!("{} Vx == kk({:#04X})", opstr, kk);
-                    println!("SKIPPING INSTRUCTION");
-                } else {
-                    println!("{} Vx != kk({:#04X})", opstr, kk);
-                    println!("NOT SKIPPING INSTRUCTION");
+            Instruction::SeImm(x, kk) => {
+                if self.v[x as usize] == kk {
+                    self.skip();
                 }
             }
-            
             // 4xkk: SNE - Skip if next instruction if Vx != kk
-            0x4000 => {
-                opstr = "SNE"; 
-                if self.v[x] != kk {
-                    self.pc += 2;
-                    println!("{} Vx != kk({:#04X})", opstr, kk);
-                    println!("SKIPPING INSTRUCTION");
-                } else {
-                    println!("{} Vx == kk({:#04X})", opstr, kk);
-                    println!("NOT SKIPPING INSTRUCTION");
+            Instruction::SneImm(x, kk) => {
+                if self.v[x as usize] != kk {
+                    self.skip();
                 }
             }
-
             // 5xy0: SE Skip if Vx == Vy
-            0x5000 => {
-                opstr = "SE"; 
-                if self.v[x] == self.v[y] {
-                    self.pc += 2;
-                    println!("{} v[{:#06X}] == v[{:#06X}]", opstr, x, y);
-                    println!("SKIPPING INSTRUCTION");
-                } else {
-                    println!("{} v[{:#06X}] != v[{:#06X}]", opstr, x, y);
-                    println!("NOT SKIPPING INSTRUCTION");
+            Instruction::SeReg(x, y) => {
+                if self.v[x as usize] == self.v[y as usize] {
+                    self.skip();
+                }
+            }
+            // 5xy2: SAVE Vx - Vy - Store Vx through Vy (either direction) at I (XO-CHIP)
+            Instruction::Save(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                self.check_mem(self.i as usize, x.abs_diff(y) + 1)?;
+                for (offset, reg) in Self::reg_range(x, y).enumerate() {
+                    self.mem[self.i as usize + offset] = self.v[reg];
+                }
+            }
+            // 5xy3: LOAD Vx - Vy - Load Vx through Vy (either direction) from I (XO-CHIP)
+            Instruction::Load(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                self.check_mem(self.i as usize, x.abs_diff(y) + 1)?;
+                for (offset, reg) in Self::reg_range(x, y).enumerate() {
+                    self.v[reg] = self.mem[self.i as usize + offset];
                 }
             }
-
             // 6xkk: LD - Set Vx to kk
-            0x6000 => {
-                opstr = "LD";
-                self.v[x] = kk;
-                println!("{} Vx = kk({:#04X})", opstr, kk);
+            Instruction::LdImm(x, kk) => {
+                self.v[x as usize] = kk;
             }
-            
             // 7xkk: ADD - Add kk to Vx
-            0x7000 => {
-                opstr = "ADD";
-                let Wrapping(_vxkk) = Wrapping(self.v[x]) + Wrapping(kk);
-                self.v[x] = _vxkk;
-                println!("{} Vx += kk({:#06X})", opstr, kk);
-            }
-            0x8000 => {
-                match self.opcode & 0x000F {
-                    // 8xy0: LD - Set Vx = Vy
-                    0x0000 => {
-                        opstr = "LD";
-                        self.v[x] = self.v[y];
-                        println!("{} Vx = Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy1: OR Vx |= Vy
-                    0x0001 => {
-                        opstr = "OR";
-                        self.v[x] |= self.v[y];
-                        println!("{} Vx |= Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy2: AND Vx &= Vy
-                    0x0002 => {
-                        opstr = "AND";
-                        self.v[x] &= self.v[y];
-                        println!("{} Vx &= Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy3: XOR Vx ^= Vy
-                    0x0003 => {
-                        opstr = "XOR";
-                        self.v[x] ^= self.v[y];
-                        println!("{} Vx &= Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy4: ADD - Add Vy to Vx
-                    0x0004 => {
-                    opstr = "ADD";
-                        let Wrapping(_vxy) = Wrapping(self.v[y]) + Wrapping(self.v[x]);
-                        self.v[x] = _vxy;
-                        if self.v[y] > self.v[x] {
-                            // Carry
-                            self.v[0xF] = 1;
-                        } else {
-                            // No carry
-                            self.v[0xF] = 0;
-                        }
-                        println!("{} Vx += Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy5: SUB Vx -= Vy
-                    0x0005 => {
-                        opstr = "SUB";
-                        let Wrapping(_vxy) = Wrapping(self.v[x]) - Wrapping(self.v[y]);
-                        self.v[x] = _vxy;
-                        println!("{} Vx -= Vy({:#06X})", opstr, self.v[y]);
-                    }
-                    // 8xy6: SHR - Shift Vx right 1 
-                    0x0006 => {
-                        opstr = "SHR";
-                        // Set VF if lsb = 1
-                        self.v[0xF] = self.v[x] & 1;
-                        self.v[x] >>= 1;
-                        println!("{} Vx >>= 1", opstr);
-                    }
-                    // 8xy7: SUBN Vx = Vy - Vx
-                    0x0007 => {
-                        opstr = "SUBN";
-                        let Wrapping(_vyx) = Wrapping(self.v[y]) - Wrapping(self.v[x]);
-                        self.v[x] = _vyx;
-                        println!("{} Vx = Vy({:#06X}) - Vx({:#06X})", opstr, self.v[y], self.v[x]);
-                    }
-                    // 8xyE: SHL - Shift Vx left 1 
-                    0x000E => {
-                        // Set VF if lsb = 1
-                        self.v[0xF] = self.v[x] >> MSB_POS;
-                        self.v[x] <<= 1;
-                        println!("{} Vx <<= 1", opstr);
-                    }
-                    _ => {
-                        eprintln!("Invalid 0x8000 opcode: {:#06X}", self.opcode);
-                    }
+            Instruction::AddImm(x, kk) => {
+                let Wrapping(_vxkk) = Wrapping(self.v[x as usize]) + Wrapping(kk);
+                self.v[x as usize] = _vxkk;
+            }
+            // 8xy0: LD - Set Vx = Vy
+            Instruction::LdReg(x, y) => {
+                self.v[x as usize] = self.v[y as usize];
+            }
+            // 8xy1: OR Vx |= Vy
+            Instruction::Or(x, y) => {
+                self.v[x as usize] |= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            // 8xy2: AND Vx &= Vy
+            Instruction::And(x, y) => {
+                self.v[x as usize] &= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
                 }
             }
+            // 8xy3: XOR Vx ^= Vy
+            Instruction::Xor(x, y) => {
+                self.v[x as usize] ^= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            // 8xy4: ADD - Add Vy to Vx
+            Instruction::Add(x, y) => {
+                // VF is written last so that it wins when x is F
+                let (sum, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = sum;
+                self.v[0xF] = carry as u8;
+            }
+            // 8xy5: SUB Vx -= Vy
+            Instruction::Sub(x, y) => {
+                // VF = NOT borrow
+                let (diff, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = diff;
+                self.v[0xF] = !borrow as u8;
+            }
+            // 8xy6: SHR - Shift Vx right 1 
+            Instruction::Shr(x, y) => {
+                let src = if self.quirks.shift_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = src >> 1;
+                // Set VF if lsb = 1
+                self.v[0xF] = src & 1;
+            }
+            // 8xy7: SUBN Vx = Vy - Vx
+            Instruction::Subn(x, y) => {
+                // VF = NOT borrow
+                let (diff, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = diff;
+                self.v[0xF] = !borrow as u8;
+            }
+            // 8xyE: SHL - Shift Vx left 1 
+            Instruction::Shl(x, y) => {
+                let src = if self.quirks.shift_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = src << 1;
+                // Set VF if msb = 1
+                self.v[0xF] = src >> MSB_POS;
+            }
             // 9xy0: SNE - Skip next instruction if Vx != Vy
-            0x9000 => {
-                opstr = "SNE";
-                if self.v[x] != self.v[y] {
-                    self.pc += 2;
-                    println!("{} Vx != Vy", opstr);
-                    println!("SKIPPING INSTRUCTION");
-                } else {
-                    println!("{} Vx == Vy", opstr);
-                    println!("NOT SKIPPING INSTRUCTION");
+            Instruction::SneReg(x, y) => {
+                if self.v[x as usize] != self.v[y as usize] {
+                    self.skip();
                 }
             }
             // Annn: LD - Set I to nnn
-            0xA000 => {
-                opstr = "LD";
+            Instruction::LdI(nnn) => {
                 self.i = nnn;
-                println!("{} I = nnn({:#03X})", opstr, nnn);
             }
-            // Bnnn: JP - Jump to nnn + V0
-            0xB000 => {
-                opstr = "JP";
-                self.pc = self.v[0x0] as usize + nnn as usize;
-                self.pc -= 2;
-                println!("{} nnn({:#05X}) + V0({:#06X})", opstr, nnn, self.v[0x0]);
+            // Bnnn: JP - Jump to nnn + V0 (or Bxnn: xnn + Vx with the jump quirk)
+            Instruction::JpV0(nnn) => {
+                let offset_reg = if self.quirks.jump_vx { (nnn >> 8) as usize } else { 0x0 };
+                self.pc = self.v[offset_reg] as usize + nnn as usize;
+                flow = Flow::Jumped;
             }
             // Cxkk - RND - Generate random number from 0-255, then & kk and store the result in Vx
-            0xC000 => {
-                opstr = "RND";
-                self.v[x] = rand::thread_rng().gen_range(0..0xFF) & kk;
-                println!("{} v[{:#03X}] = {:#04X}", opstr, x, kk);
-            }
-            // Dxyn: DRW - Draw
-            0xD000 => {
-                opstr = "DRW";
-                // Reduce if overflow
-                if self.v[x] >= DISP_X as u8 {
-                    println!("Performing modulo reduction for x-axis");
-                    self.v[x] %= DISP_X as u8; 
-                }
-                if self.v[y] > DISP_Y as u8 {
-                    println!("Performing modulo reduction for y-axis");
-                    self.v[y] %= DISP_Y as u8; 
+            Instruction::Rnd(x, kk) => {
+                self.v[x as usize] = self.rng.next_byte(&self.mem) & kk;
+            }
+            // Dxyn: DRW - Draw an 8xn sprite at (Vx, Vy)
+            // Dxy0: DRW - Draw a 16x16 sprite (SUPER-CHIP)
+            Instruction::Drw(x, y, n) => {
+                self.draw_sprite(x as usize, y as usize, n)?;
+            }
+            // Ex9E: SKP - Skip next instruction if key with Vx is pressed
+            Instruction::Skp(x) => {
+                if self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip();
                 }
-                self.v[0xF] = 0x0;
-
-                for dy in 0..n as usize {
-                    let px = self.mem[self.i as usize + dy];
-                    for dx in 0..8 as usize {
-                        if px & (0x80 >> dx) != 0 {
-                            // destination pixel
-                            let mut dest = self.v[x] as usize + dx + ((self.v[y] as usize + dy) * DISP_X);
-                            // I can't pinpoint why this overflows but I believe this is intended
-                            // behavior 
-                            if dest >= DISP_X * DISP_Y {
-                                dest -= DISP_X * DISP_Y;
-                            }
-                            // If a pixel is drawn to an already drawn pixel, it is unset
-                            if self.gfx[dest] != 0{
-                                // Indicate that pixel was unset
-                                self.v[0xF] = 1;
-                            }
-                            // Set the pixels to the screen. 
-                            // XOR'ing an already set bit will naturally unset it if it is 
-                            // already set.
-                            self.gfx[dest] ^= 1;
-                        }
-                    }
+            }
+            // ExA1: SKNP - Skip next instruction if key with Vx is not presed
+            Instruction::Sknp(x) => {
+                if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip();
                 }
-                println!("{} Vx({:#06X}) Vy({:#06X})", opstr, self.v[y], self.v[x]);
-                self.draw_flag = true;
             }
-            0xE000 => {
-                match self.opcode & 0x000F {
-                    // Ex9E: SKP - Skip next instruction if key with Vx is pressed
-                    0x000E => {
-                        opstr = "SKP";
-                        if self.keys[self.v[x] as usize] {
-                            println!("{} Key[Vx({:#X})] is pressed", opstr, self.v[x]);
-                            println!("SKIPPING INSTRUCTION");
-                            self.pc += 2;
-                        } else {
-                            println!("{} Key[Vx({:#X})] is not pressed", opstr, self.v[x]);
-                            println!("NOT SKIPPING INSTRUCTION");
-                        }
-                    }
-                    // ExA1: SKNP - Skip next instruction if key with Vx is not presed
-                    0x0001 => {
-                        if !self.keys[self.v[x] as usize] {
-                            println!("{} Key[Vx({:#X})] is not pressed", opstr, self.v[x]);
-                            println!("SKIPPING INSTRUCTION");
-                            self.pc += 2;
-                        } else {
-                            println!("{} Key[Vx({:#X})] is pressed", opstr, self.v[x]);
-                            println!("NOT SKIPPING INSTRUCTION");
-                        }
+            // F000 nnnn: LD I, nnnn - Load a 16 bit address into I (XO-CHIP)
+            Instruction::LdILong => {
+                let addr = self.pc + 2;
+                self.check_mem(addr, 2)?;
+                self.i = ((self.mem[addr] as u16) << 8) | self.mem[addr + 1] as u16;
+                self.pc += 2;
+            }
+            // Fn01: PLANE n - Select the bitplanes to draw on (XO-CHIP)
+            Instruction::Plane(n) => {
+                self.plane = n & 0x3;
+            }
+            // Fx07: LD Vx, DT - Set Vx = delay timer value.
+            Instruction::LdVxDt(x) => {
+                self.v[x as usize] = self.dt;
+            }
+            // Fx0A: LD Vx, K - Wait for a key press and store the value of the key in Vx.
+            // Like the VIP, the key only counts once it is released again. Rather
+            // than block, the instruction repeats itself until then so the timers
+            // and display keep running.
+            Instruction::LdVxK(x) => {
+                match self.key_wait {
+                    Some(key_idx) if !self.keys[key_idx as usize] => {
+                        self.key_wait = None;
+                        self.v[x as usize] = key_idx;
                     }
-                    _ => {
-                        eprintln!("Invalid 0xE000 opcode {:#06X}", self.opcode);
+                    Some(_) => flow = Flow::Jumped,
+                    None => {
+                        self.key_wait = self.keys.iter().position(|&k| k).map(|k| k as u8);
+                        flow = Flow::Jumped;
                     }
                 }
             }
-            0xF000 => {
-                match self.opcode & 0x00FF {
-                    
-                    // Fx07: LD Vx, DT - Set Vx = delay timer value.
-                    0x0007 => {
-                        opstr = "LD";
-                        self.v[x] = self.dt;
-                        println!("{} Vx = dt({:#04X})", opstr, self.dt);
-                    }
-                    // TODO
-                    // Fx0A: LD Vx, K - Wait for a key press and store the value of the key in Vx.
-                    0x000A => {
-                        opstr = "LD";
-                        let key_idx = input_handler.wait_for_key(sdl_context);
-                        self.v[x] = key_idx;
-                        println!("{} Vx, K{:#06X}", opstr, key_idx);
-                    }
-                    // Fx15: LD DT, Vx - Set delay timer = Vx.
-                    0x0015 => {
-                        opstr = "LD";
-                        self.dt = self.v[x];
-                        println!("{} dt = Vx({:#06X})", opstr, self.dt);
-                    }
-                    // Fx18: LD ST, Vx - Set sound timer = Vx.
-                    0x0018 => {
-                        opstr = "LD";
-                        self.st = self.v[x];
-                        println!("{} st = Vx({:#06X})", opstr, self.v[x]);
-                    }
-                    // Fx1E: ADD I, Vx ---- I += Vx
-                    0x001E => {
-                        opstr = "ADD";
-                        self.i += self.v[x] as u16;
-                        println!("{} i += Vx({:#06X})", opstr, self.v[x]);
-                    }
-                    // Fx29: LD F, Vx - Set I = location of sprite for digit Vx.
-                    0x0029 => {
-                        opstr = "LD";
-                        self.i = self.v[x] as u16 * 0x5;
-                        println!("{} i = Vx({:#05X}) * 0x5", opstr, self.v[x]);
-                    }
-                    // Fx33: LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                    0x0033 => {
-                        opstr = "LD";
-                        // Load 100s place
-                        self.mem[self.i as usize] = self.v[x] / 100;
-                        // Load 10s place
-                        self.mem[self.i as usize + 1] = (self.v[x] / 10) % 10;
-                        // Loads 1s place
-                        self.mem[self.i as usize + 2] = self.v[x] % 10;
-                        println!("{} mem = Vx BCD", opstr);
+            // Fx15: LD DT, Vx - Set delay timer = Vx.
+            Instruction::LdDtVx(x) => {
+                self.dt = self.v[x as usize];
+            }
+            // Fx18: LD ST, Vx - Set sound timer = Vx.
+            Instruction::LdStVx(x) => {
+                self.st = self.v[x as usize];
+            }
+            // Fx1E: ADD I, Vx ---- I += Vx
+            Instruction::AddIVx(x) => {
+                self.i = self.i.wrapping_add(self.v[x as usize] as u16);
+            }
+            // Fx29: LD F, Vx - Set I = location of sprite for digit Vx.
+            Instruction::LdFVx(x) => {
+                self.i = FONT_ADDR as u16 + self.v[x as usize] as u16 * 0x5;
+            }
+            // Fx30: LD HF, Vx - Set I = location of big (10 byte) sprite for digit Vx (SUPER-CHIP)
+            Instruction::LdHfVx(x) => {
+                self.i = BIG_FONT_ADDR as u16 + (self.v[x as usize] & 0xF) as u16 * 10;
+            }
+            // Fx33: LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2.
+            Instruction::LdBVx(x) => {
+                let vx = self.v[x as usize];
+                self.check_mem(self.i as usize, 3)?;
+                // Load 100s place
+                self.mem[self.i as usize] = vx / 100;
+                // Load 10s place
+                self.mem[self.i as usize + 1] = (vx / 10) % 10;
+                // Loads 1s place
+                self.mem[self.i as usize + 2] = vx % 10;
+            }
+            // Fx55: LD [I], Vx - Store registers V0 through Vx in memory starting at location I.
+            Instruction::LdIVx(x) => {
+                let x = x as usize;
+                self.check_mem(self.i as usize, x + 1)?;
+                for i in 0..x+1 {
+                    self.mem[self.i as usize + i] = self.v[i];
+                }
+                self.advance_i(x);
+            }
+            // Fx65: LD Vx, [I] - Read from memory starting at location I and store it into registers V0 through Vx.
+            Instruction::LdVxI(x) => {
+                let x = x as usize;
+                self.check_mem(self.i as usize, x + 1)?;
+                for i in 0..x+1 {
+                   self.v[i] = self.mem[self.i as usize + i]; 
+                }
+                self.advance_i(x);
+            }
+            // Fx75: LD R, Vx - Store V0 through Vx in the RPL user flags (SUPER-CHIP)
+            Instruction::LdRVx(x) => {
+                let count = (x as usize + 1).min(self.num_rpl());
+                self.rpl[0..count].copy_from_slice(&self.v[0..count]);
+            }
+            // Fx85: LD Vx, R - Read V0 through Vx from the RPL user flags (SUPER-CHIP)
+            Instruction::LdVxR(x) => {
+                let count = (x as usize + 1).min(self.num_rpl());
+                self.v[0..count].copy_from_slice(&self.rpl[0..count]);
+            }
+        }
+        Ok((instruction, flow))
+    }
+
+    // Draws an 8xn sprite (or 16x16 when n is 0) from I at (Vx, Vy) on the
+    // selected planes, setting VF on collision
+    fn draw_sprite(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error> {
+        let (disp_x, disp_y) = (self.disp_x(), self.disp_y());
+        // A 16x16 sprite is stored as 16 rows of 2 bytes
+        let (width, height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+        let row_bytes = width / 8;
+        // With several planes selected, each plane's sprite follows
+        // the previous one's in memory
+        let mut sprite_addr = self.i as usize;
+        let planes = (self.plane & 0x1) + ((self.plane >> 1) & 0x1);
+        self.check_mem(sprite_addr, planes as usize * height * row_bytes)?;
+        // The starting position always wraps, only the sprite body is
+        // subject to the clipping quirk
+        let x0 = self.v[x] as usize % disp_x;
+        let y0 = self.v[y] as usize % disp_y;
+        self.v[0xF] = 0x0;
+
+        for plane_bit in (0..NUM_PLANES).map(|p| 1u8 << p) {
+            if self.plane & plane_bit == 0 {
+                continue;
+            }
+            for dy in 0..height {
+                let mut py = y0 + dy;
+                if py >= disp_y {
+                    if self.quirks.clip_sprites {
+                        break;
                     }
-                    // Fx55: LD [I], Vx - Store registers V0 through Vx in memory starting at location I.
-                    0x0055 => {
-                        opstr = "LD";
-                        for i in 0..x+1 {
-                            self.mem[self.i as usize + i] = self.v[i];
+                    py %= disp_y;
+                }
+                let addr = sprite_addr + dy * row_bytes;
+                let row = if row_bytes == 2 {
+                    ((self.mem[addr] as u16) << 8) | self.mem[addr + 1] as u16
+                } else {
+                    (self.mem[addr] as u16) << 8
+                };
+                for dx in 0..width {
+                    if row & (0x8000 >> dx) != 0 {
+                        let mut col = x0 + dx;
+                        if col >= disp_x {
+                            if self.quirks.clip_sprites {
+                                break;
+                            }
+                            col %= disp_x;
                         }
-                        println!("{} mem = V0-Vx({:#04X}) + 0x5", opstr, x);
-                    }
-                    // Fx65: LD Vx, [I] - Read from memory starting at location I and store it into registers V0 through Vx.
-                    0x0065 => {
-                        opstr = "LD";
-                        for i in 0..x+1 {
-                           self.v[i] = self.mem[self.i as usize + i]; 
+                        // destination pixel
+                        let dest = col + py * disp_x;
+                        // If a pixel is drawn to an already drawn pixel, it is unset
+                        if self.gfx[dest] & plane_bit != 0{
+                            // Indicate that pixel was unset
+                            self.v[0xF] = 1;
                         }
-                        println!("{} V0-Vx({:#X}) = mem", opstr, x);
+                        // Set the pixels to the screen. 
+                        // XOR'ing an already set bit will naturally unset it if it is 
+                        // already set.
+                        self.gfx[dest] ^= plane_bit;
                     }
-                    _ => eprintln!("Invalid 0xF000 opcode {:#06X}", self.opcode)
                 }
             }
-            _ => eprintln!("Invalid opcode {:#06X}", self.opcode)
+            sprite_addr += height * row_bytes;
         }
+        self.draw_flag = true;
+        Ok(())
     }
     
-    pub fn print_registers(self: &Self){
+    // Registers Vx through Vy in the order 5xy2/5xy3 visit them
+    fn reg_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    // Moves I on after Fx55/Fx65 transferred V0 through Vx
+    fn advance_i(&mut self, x: usize){
+        match self.quirks.load_store_i {
+            LoadStoreI::Keep => {}
+            LoadStoreI::AddXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+            LoadStoreI::AddX => self.i = self.i.wrapping_add(x as u16),
+        }
+    }
+
+    // Number of RPL flags Fx75/Fx85 can reach
+    fn num_rpl(&self) -> usize {
+        if self.xo_chip { NUM_VREGS } else { NUM_RPL }
+    }
+
+    pub fn print_registers(&self){
         println!("------------------------");
+        println!("PC: {:#05X}  SP: {}", self.pc, self.stack.len());
         println!("V REGISTERS:");
         for i in 0..NUM_VREGS {
             print!("{:#03X}  ", i);
         }
         println!();
 
-        for i in 0..NUM_VREGS {
-            print!("{:#04X} ", self.v[i]);
+        for vx in self.v.iter() {
+            print!("{:#04X} ", vx);
         }
         println!();
 
@@ -496,18 +982,349 @@ impl Chip8 {
         println!("ST: {:#04X}", self.st);
     }
 
-    pub fn print_screen(self: &mut Self){
+    pub fn print_screen(&mut self){
         if self.draw_flag {
-            for (i, &px) in self.gfx.iter().enumerate() {
+            let disp_x = self.disp_x();
+            for (i, &px) in self.screen().iter().enumerate() {
                 if px != 0 {
                     print!("{}", PX);
                 } else {
                     print!("  ");
                 }
-                if (i + 1) % DISP_X == 0 {
+                if (i + 1) % disp_x == 0 {
                     println!();
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceLevel;
+
+    fn machine(program: &[u8]) -> Chip8 {
+        let mut c8 = Chip8::new();
+        c8.load_font();
+        c8.load_rom_bytes(program).unwrap();
+        c8
+    }
+
+    fn run(c8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            c8.cycle().unwrap();
+        }
+    }
+
+    // XO-CHIP machine running a program given as opcodes
+    fn xo_machine(program: &[u16]) -> Chip8 {
+        let mut c8 = Chip8::with_quirks(Quirks::XO_CHIP);
+        c8.set_xo_chip(true);
+        c8.load_font();
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        c8.load_rom_bytes(&rom).unwrap();
+        c8
+    }
+
+    // (x, y) of every lit pixel on screen
+    fn lit(c8: &Chip8) -> Vec<(usize, usize)> {
+        let width = c8.disp_x();
+        c8.screen().iter().enumerate().filter(|&(_, &px)| px != 0).map(|(n, _)| (n % width, n / width)).collect()
+    }
+
+    fn plot(c8: &mut Chip8, x: usize, y: usize) {
+        let width = c8.disp_x();
+        c8.gfx[y * width + x] = 1;
+    }
+
+    #[test]
+    fn stack_overflows_and_underflows() {
+        // Calls itself forever
+        let mut c8 = machine(&[0x22, 0x00]);
+        run(&mut c8, STACK_SIZE);
+        assert!(matches!(c8.cycle(), Err(Chip8Error::StackOverflow { pc: 0x200 })));
+        assert_eq!(c8.stack().len(), STACK_SIZE);
+
+        let mut c8 = machine(&[0x00, 0xEE]);
+        assert!(matches!(c8.cycle(), Err(Chip8Error::StackUnderflow { pc: 0x200 })));
+    }
+
+    #[test]
+    fn memory_past_the_end_is_out_of_bounds() {
+        // LD I, 0xFFF then LD V1, [I] reads 0xFFF and 0x1000
+        let mut c8 = machine(&[0xAF, 0xFF, 0xF1, 0x65]);
+        run(&mut c8, 1);
+        assert!(matches!(c8.cycle(), Err(Chip8Error::MemoryOutOfBounds { pc: 0x202, addr: 0x1000 })));
+        // One register still fits
+        let mut c8 = machine(&[0xAF, 0xFF, 0xF0, 0x65]);
+        run(&mut c8, 2);
+
+        // BCD and sprites check I the same way
+        for program in [[0xAF, 0xFE, 0xF0, 0x33], [0xAF, 0xFC, 0xD0, 0x05]] {
+            let mut c8 = machine(&program);
+            run(&mut c8, 1);
+            assert!(matches!(c8.cycle(), Err(Chip8Error::MemoryOutOfBounds { pc: 0x202, .. })));
+        }
+
+        // Fetching from the last byte
+        let mut c8 = machine(&[]);
+        c8.set_pc(0xFFF);
+        assert!(matches!(c8.cycle(), Err(Chip8Error::MemoryOutOfBounds { pc: 0xFFF, addr: 0x1000 })));
+    }
+
+    #[test]
+    fn invalid_opcodes_fault() {
+        for opcode in [0x5121u16, 0x8008, 0x900F, 0xE000, 0xF0FF, 0xFFFF] {
+            let mut c8 = machine(&opcode.to_be_bytes());
+            match c8.cycle() {
+                Err(Chip8Error::InvalidOpcode { pc: 0x200, opcode: found }) => assert_eq!(found, opcode),
+                other => panic!("{:04X} gave {:?}", opcode, other),
+            }
+        }
+        // XO-CHIP instructions only exist with XO-CHIP on
+        for opcode in [0x00D1u16, 0x5122, 0x5123, 0xF000, 0xF201] {
+            let mut c8 = machine(&opcode.to_be_bytes());
+            assert!(matches!(c8.cycle(), Err(Chip8Error::InvalidOpcode { .. })), "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn sys_calls_are_skipped() {
+        // SYS 000, SYS 123, and 0230 outside HIRES, then LD V0, 0x01
+        let mut c8 = machine(&[0x00, 0x00, 0x01, 0x23, 0x02, 0x30, 0x60, 0x01]);
+        c8.gfx[0] = 1;
+        run(&mut c8, 4);
+        assert_eq!(c8.pc(), 0x208);
+        assert_eq!(c8.v()[0], 1);
+        assert_eq!(c8.gfx[0], 1);
+    }
+
+    #[test]
+    fn error_policies() {
+        // RET with an empty stack, then LD V0, 0x01
+        let program = [0x00, 0xEE, 0x60, 0x01];
+
+        // Halt and trap both leave the PC on the faulting instruction, what
+        // happens next is up to the frontend
+        for policy in [ErrorPolicy::Halt, ErrorPolicy::Trap] {
+            let mut c8 = machine(&program);
+            c8.error_policy = policy;
+            assert!(c8.cycle().is_err());
+            assert!(c8.cycle().is_err());
+            assert_eq!(c8.pc(), 0x200);
+        }
+
+        // Ignore skips it and logs it to the trace output
+        let path = std::env::temp_dir().join(format!("chip8-ignore-{}.log", std::process::id()));
+        let mut c8 = machine(&program);
+        c8.error_policy = ErrorPolicy::Ignore;
+        c8.tracer = Tracer::to_file(TraceLevel::Off, path.to_str().unwrap()).unwrap();
+        run(&mut c8, 2);
+        assert_eq!(c8.pc(), 0x204);
+        assert_eq!(c8.v()[0], 1);
+        c8.tracer.flush();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(log.trim(), "Ignoring stack underflow (RET with an empty stack) at 0x200");
+    }
+
+    #[test]
+    fn high_and_low_switch_resolution() {
+        // HIGH, LOW
+        let mut c8 = machine(&[0x00, 0xFF, 0x00, 0xFE]);
+        plot(&mut c8, 3, 3);
+        run(&mut c8, 1);
+        assert_eq!((c8.disp_x(), c8.disp_y()), (HIRES_X, HIRES_Y));
+        assert!(lit(&c8).is_empty());
+        plot(&mut c8, 100, 60);
+        c8.draw_flag = false;
+        run(&mut c8, 1);
+        assert_eq!((c8.disp_x(), c8.disp_y()), (DISP_X, DISP_Y));
+        assert!(c8.draw_flag);
+        assert!(c8.gfx.iter().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn scrolling() {
+        // HIGH, SCD 3, SCR, SCL, SCL, SCL
+        let mut c8 = machine(&[0x00, 0xFF, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC, 0x00, 0xFC]);
+        run(&mut c8, 1);
+        plot(&mut c8, 10, 5);
+        plot(&mut c8, 0, 62);
+        run(&mut c8, 1);
+        // The bottom row scrolled off
+        assert_eq!(lit(&c8), [(10, 8)]);
+        run(&mut c8, 1);
+        assert_eq!(lit(&c8), [(14, 8)]);
+        run(&mut c8, 1);
+        assert_eq!(lit(&c8), [(10, 8)]);
+        run(&mut c8, 2);
+        assert_eq!(lit(&c8), [(2, 8)]);
+
+        // Low resolution scrolls by the same number of its own pixels
+        let mut c8 = machine(&[0x00, 0xC1, 0x00, 0xFB]);
+        plot(&mut c8, 60, 0);
+        run(&mut c8, 1);
+        assert_eq!(lit(&c8), [(60, 1)]);
+        run(&mut c8, 1);
+        assert!(lit(&c8).is_empty());
+    }
+
+    #[test]
+    fn big_sprites_are_16x16() {
+        // HIGH, LD I, sprite, LD V0, 5, LD V1, 3, DRW V0, V1, 0 twice
+        let mut program = vec![0x00, 0xFF, 0xA2, 0x0E, 0x60, 0x05, 0x61, 0x03, 0xD0, 0x10, 0xD0, 0x10, 0x12, 0x0C];
+        // Solid but for the last row, which only has its corners
+        program.extend([0xFF; 30]);
+        program.extend([0x80, 0x01]);
+        let mut c8 = machine(&program);
+        run(&mut c8, 5);
+        let pixels = lit(&c8);
+        assert_eq!(pixels.len(), 15 * 16 + 2);
+        assert!(pixels.contains(&(5, 3)) && pixels.contains(&(20, 17)));
+        assert!(pixels.contains(&(5, 18)) && pixels.contains(&(20, 18)));
+        assert!(!pixels.contains(&(6, 18)) && !pixels.contains(&(21, 3)));
+        assert_eq!(c8.v()[0xF], 0);
+        // Drawing it again erases it and reports the collision
+        run(&mut c8, 1);
+        assert!(lit(&c8).is_empty());
+        assert_eq!(c8.v()[0xF], 1);
+    }
+
+    #[test]
+    fn big_font_digits() {
+        // LD V0, 0x1B, LD HF, V0, DRW V1, V1, 0xA
+        let mut c8 = machine(&[0x60, 0x1B, 0xF0, 0x30, 0xD1, 0x1A]);
+        run(&mut c8, 2);
+        // Only the low nibble counts
+        assert_eq!(c8.i() as usize, BIG_FONT_ADDR + 0xB * 10);
+        assert_eq!(c8.mem()[c8.i() as usize..c8.i() as usize + 10], BIG_FONT[110..120]);
+        run(&mut c8, 1);
+        let top: Vec<usize> = lit(&c8).into_iter().filter(|&(_, y)| y == 0).map(|(x, _)| x).collect();
+        assert_eq!(top, (0..8).filter(|x| BIG_FONT[110] & (0x80 >> x) != 0).collect::<Vec<_>>());
+        assert_eq!(c8.v()[0xF], 0);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // LD R, V9 then LD V9, R
+        let mut c8 = machine(&[0xF9, 0x75, 0xF9, 0x85]);
+        for x in 0..NUM_VREGS {
+            c8.set_v(x, x as u8 + 1);
+        }
+        run(&mut c8, 1);
+        for x in 0..NUM_VREGS {
+            c8.set_v(x, 0);
+        }
+        run(&mut c8, 1);
+        // SUPER-CHIP only has 8 flags, V8 and V9 don't make it there and back
+        assert_eq!(c8.v()[..10], [1, 2, 3, 4, 5, 6, 7, 8, 0, 0]);
+
+        // XO-CHIP has 16
+        let mut c8 = Chip8::new();
+        c8.set_xo_chip(true);
+        c8.load_rom_bytes(&[0xF9, 0x75, 0xF9, 0x85]).unwrap();
+        for x in 0..NUM_VREGS {
+            c8.set_v(x, x as u8 + 1);
+        }
+        run(&mut c8, 1);
+        for x in 0..NUM_VREGS {
+            c8.set_v(x, 0);
+        }
+        run(&mut c8, 1);
+        assert_eq!(c8.v()[..11], [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 0]);
+    }
+
+    #[test]
+    fn long_i_loads_are_skipped_whole() {
+        let mut c8 = xo_machine(&[
+            0xF000, 0x1234, // LD I, LONG 0x1234
+            0x3000, 0xF000, 0xABCD, // SE V0, 0 skips all four bytes
+            0x4000, 0xF000, 0xFFFE, // SNE V0, 0 doesn't skip
+            0xF165, // LD V1, [I]
+        ]);
+        run(&mut c8, 1);
+        assert_eq!((c8.i(), c8.pc()), (0x1234, 0x204));
+        run(&mut c8, 1);
+        assert_eq!((c8.i(), c8.pc()), (0x1234, 0x20A));
+        run(&mut c8, 2);
+        assert_eq!((c8.i(), c8.pc()), (0xFFFE, 0x210));
+        // All 64 KiB can be reached
+        c8.mem_mut()[0xFFFE..].copy_from_slice(&[7, 9]);
+        run(&mut c8, 1);
+        assert_eq!(c8.v()[..2], [7, 9]);
+
+        // Without XO-CHIP F000 is an ordinary two byte invalid opcode, so
+        // a skip only steps over its first half
+        let mut c8 = machine(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        run(&mut c8, 1);
+        assert_eq!(c8.pc(), 0x204);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        let mut c8 = xo_machine(&[
+            0xA300, 0x5142, // SAVE V1 - V4
+            0xA310, 0x5412, // SAVE V4 - V1, backwards
+            0xA320, 0x5552, // SAVE V5 - V5
+            0xA300, 0x5A83, // LOAD VA - V8, backwards
+            0xA310, 0x5B63, // LOAD VB - V6, backwards
+        ]);
+        for x in 1..=5 {
+            c8.set_v(x, x as u8);
+        }
+        run(&mut c8, 6);
+        assert_eq!(c8.mem()[0x300..0x305], [1, 2, 3, 4, 0]);
+        assert_eq!(c8.mem()[0x310..0x315], [4, 3, 2, 1, 0]);
+        assert_eq!(c8.mem()[0x320..0x322], [5, 0]);
+        // I is left alone
+        assert_eq!(c8.i(), 0x320);
+        run(&mut c8, 2);
+        assert_eq!(c8.v()[8..11], [3, 2, 1]);
+        run(&mut c8, 2);
+        assert_eq!(c8.v()[6..12], [0, 0, 1, 2, 3, 4]);
+        assert_eq!(c8.i(), 0x310);
+    }
+
+    #[test]
+    fn planes() {
+        let mut c8 = xo_machine(&[
+            0xF201, 0xA216, 0xD001, // plane 2
+            0xF101, 0xD001, // plane 1
+            0xF301, 0xD001, // both, each plane with its own row
+            0xF001, 0xD001, // none
+            0xF101, 0x00E0, // clears plane 1 only
+            0x80C0, // the sprite rows
+        ]);
+        run(&mut c8, 3);
+        assert_eq!(c8.gfx[..2], [2, 0]);
+        assert_eq!(c8.v()[0xF], 0);
+        run(&mut c8, 2);
+        assert_eq!(c8.gfx[..2], [3, 0]);
+        assert_eq!(c8.v()[0xF], 0);
+        run(&mut c8, 2);
+        assert_eq!(c8.gfx[..2], [0, 2]);
+        assert_eq!(c8.v()[0xF], 1);
+        run(&mut c8, 2);
+        assert_eq!(c8.gfx[..2], [0, 2]);
+        assert_eq!(c8.v()[0xF], 0);
+        c8.gfx[0] = 3;
+        run(&mut c8, 2);
+        assert_eq!(c8.gfx[..2], [2, 2]);
+    }
+
+    #[test]
+    fn scroll_up_moves_the_selected_planes() {
+        // SCU 2, then PLANE 3 and SCU 1
+        let mut c8 = xo_machine(&[0x00D2, 0xF301, 0x00D1]);
+        c8.gfx[5 * DISP_X + 5] = 3;
+        run(&mut c8, 1);
+        assert_eq!(c8.gfx[3 * DISP_X + 5], 1);
+        assert_eq!(c8.gfx[5 * DISP_X + 5], 2);
+        run(&mut c8, 2);
+        assert_eq!(lit(&c8), [(5, 2), (5, 4)]);
+        assert_eq!(c8.gfx[2 * DISP_X + 5], 1);
+        assert_eq!(c8.gfx[4 * DISP_X + 5], 2);
+    }
+}
@@ -0,0 +1,324 @@
+// Software CRT look: scanlines, gaps between pixels, glow around lit pixels
+// and a slightly curved screen. Everything is done on the CPU while scaling
+// the picture up to its size in the window, so no shader support is needed.
+// Each effect has a strength from 0 (off) to 1.
+use crate::palette::Rgb;
+
+// Curvature 1 stretches the middle of each edge out by this much
+const MAX_BULGE: f32 = 0.25;
+// Weights of the blur spreading the glow, over -2..=2 pixels
+const BLUR: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+type Color = (f32, f32, f32);
+
+// Fractions are worked with as 0 to ONE, so pixels can be done in integers
+const ONE: u32 = 256;
+
+fn fixed(fraction: f32) -> u32 {
+    (fraction.clamp(0.0, 1.0) * ONE as f32).round() as u32
+}
+
+// Where a window column (or row) falls on the CHIP-8 screen
+struct Sample {
+    // The CHIP-8 pixel it shows
+    pixel: usize,
+    // It is the last window pixel of that CHIP-8 pixel
+    gap: bool,
+    // Distance from the middle of the pixel, -1 to 1
+    edge: f32,
+    // The two nearest pixel centres and how far it is between them, for the glow
+    near: (usize, usize, u32),
+}
+
+impl Sample {
+    fn new(n: usize, out_len: usize, len: usize) -> Self {
+        let step = len as f32 / out_len as f32;
+        let at = (n as f32 + 0.5) * step;
+        let pixel = (at as usize).min(len - 1);
+        let frac = at - pixel as f32;
+        let centre = (at - 0.5).clamp(0.0, (len - 1) as f32);
+        let first = centre as usize;
+        Self {
+            pixel,
+            gap: frac >= 1.0 - step,
+            edge: 2.0 * frac - 1.0,
+            near: (first, (first + 1).min(len - 1), fixed(centre - first as f32)),
+        }
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, a.2 + (b.2 - a.2) * t)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Crt {
+    // How much the top and bottom of every CHIP-8 row are darkened
+    pub scanlines: f32,
+    // How much the last window pixel of every CHIP-8 pixel, in both
+    // directions, is darkened
+    pub grid: f32,
+    // How much light bleeds from lit pixels into darker surroundings
+    pub bloom: f32,
+    // How far the image bulges out like the glass of a tube
+    pub curvature: f32,
+    // The frame blurred, at CHIP-8 resolution
+    glow: Vec<Color>,
+    // The picture before curving it
+    flat: Vec<u8>,
+    // For every output pixel, the pixel of flat it shows (None past the
+    // edge of the tube), and the output size and bulge it was made for
+    curve: Vec<Option<u32>>,
+    curve_for: (usize, usize, f32),
+    // RGB24 output, 3 bytes per pixel, no padding
+    out: Vec<u8>,
+}
+
+impl Crt {
+    // At least one effect is turned on
+    pub fn enabled(&self) -> bool {
+        self.scanlines > 0.0 || self.grid > 0.0 || self.bloom > 0.0 || self.curvature > 0.0
+    }
+
+    // Scales a width x height frame of colours (row-major, e.g. from
+    // Phosphor::apply) to out_w x out_h with the effects applied. Returns
+    // RGB24, out_w * 3 bytes per row.
+    pub fn render(&mut self, colors: &[Rgb], width: usize, height: usize, out_w: usize, out_h: usize) -> &[u8] {
+        self.out.clear();
+        self.out.resize(out_w * out_h * 3, 0);
+        if width == 0 || height == 0 || out_w == 0 || out_h == 0 {
+            return &self.out;
+        }
+        let bulge = self.curvature.clamp(0.0, 1.0) * MAX_BULGE;
+        // Draw straight into the output unless it still has to be curved
+        let mut image = if bulge > 0.0 { std::mem::take(&mut self.flat) } else { std::mem::take(&mut self.out) };
+        image.resize(out_w * out_h * 3, 0);
+        self.scale(colors, width, height, out_w, out_h, &mut image);
+        if bulge > 0.0 {
+            self.bend(out_w, out_h, bulge, &image);
+            self.flat = image;
+        } else {
+            self.out = image;
+        }
+        &self.out
+    }
+
+    // Scales the frame up into image with scanlines, gaps and glow
+    fn scale(&mut self, colors: &[Rgb], width: usize, height: usize, out_w: usize, out_h: usize, image: &mut [u8]) {
+        let bloom = fixed(self.bloom);
+        // Gaps need a few window pixels per CHIP-8 pixel, or there would be
+        // nothing left but gaps
+        let gap_shade = if out_w >= width * 2 && out_h >= height * 2 { ONE - fixed(self.grid) } else { ONE };
+        if bloom > 0 {
+            self.blur(colors, width, height);
+        }
+        // Everything about a window pixel depends only on its column and its
+        // row, so work those out once
+        let columns: Vec<Sample> = (0..out_w).map(|x| Sample::new(x, out_w, width)).collect();
+        let mut glow_line = vec![[0; 3]; width];
+        for (y, line) in image.chunks_mut(out_w * 3).enumerate() {
+            let row = Sample::new(y, out_h, height);
+            let shade = fixed(1.0 - self.scanlines.clamp(0.0, 1.0) * row.edge * row.edge);
+            if bloom > 0 {
+                let (above, below, t) = row.near;
+                let t = t as f32 / ONE as f32;
+                for (x, glow) in glow_line.iter_mut().enumerate() {
+                    let (r, g, b) = lerp(self.glow[above * width + x], self.glow[below * width + x], t);
+                    *glow = [r.round() as u32, g.round() as u32, b.round() as u32];
+                }
+            }
+            let pixels = &colors[row.pixel * width..(row.pixel + 1) * width];
+            for (column, rgb) in columns.iter().zip(line.chunks_mut(3)) {
+                let shade = if row.gap || column.gap { shade * gap_shade / ONE } else { shade };
+                let (r, g, b) = pixels[column.pixel];
+                let mut color = [r as u32 * shade / ONE, g as u32 * shade / ONE, b as u32 * shade / ONE];
+                if bloom > 0 {
+                    let (left, right, t) = column.near;
+                    for (c, (a, b)) in color.iter_mut().zip(glow_line[left].iter().zip(glow_line[right])) {
+                        // Only adds light where the surroundings are brighter
+                        let glow = (a * (ONE - t) + b * t) / ONE;
+                        if glow > *c {
+                            *c += (glow - *c) * bloom / ONE;
+                        }
+                    }
+                }
+                rgb[0] = color[0].min(255) as u8;
+                rgb[1] = color[1].min(255) as u8;
+                rgb[2] = color[2].min(255) as u8;
+            }
+        }
+    }
+
+    // Copies image into the output bulging out from the middle, leaving
+    // black where it curves away
+    fn bend(&mut self, out_w: usize, out_h: usize, bulge: f32, image: &[u8]) {
+        if self.curve_for != (out_w, out_h, bulge) {
+            self.curve_for = (out_w, out_h, bulge);
+            self.curve.clear();
+            for y in 0..out_h {
+                for x in 0..out_w {
+                    // -1 to 1 across the screen
+                    let u = (x as f32 + 0.5) / out_w as f32 * 2.0 - 1.0;
+                    let v = (y as f32 + 0.5) / out_h as f32 * 2.0 - 1.0;
+                    let (u, v) = (u * (1.0 + bulge * v * v), v * (1.0 + bulge * u * u));
+                    let inside = u.abs() < 1.0 && v.abs() < 1.0;
+                    self.curve.push(inside.then(|| {
+                        let sx = (((u + 1.0) / 2.0 * out_w as f32) as usize).min(out_w - 1);
+                        let sy = (((v + 1.0) / 2.0 * out_h as f32) as usize).min(out_h - 1);
+                        (sy * out_w + sx) as u32
+                    }));
+                }
+            }
+        }
+        for (from, rgb) in self.curve.iter().zip(self.out.chunks_mut(3)) {
+            if let Some(n) = from.map(|n| n as usize) {
+                rgb.copy_from_slice(&image[n * 3..n * 3 + 3]);
+            }
+        }
+    }
+
+    // Fills glow with the frame blurred horizontally and then vertically,
+    // treating everything past the edges as the nearest edge pixel
+    fn blur(&mut self, colors: &[Rgb], width: usize, height: usize) {
+        let mut rows = vec![(0.0, 0.0, 0.0); width * height];
+        for y in 0..height {
+            for x in 0..width {
+                rows[y * width + x] = blur_at(|i| {
+                    let (r, g, b) = colors[y * width + (x as isize + i).clamp(0, width as isize - 1) as usize];
+                    (r as f32, g as f32, b as f32)
+                });
+            }
+        }
+        self.glow.clear();
+        for y in 0..height {
+            for x in 0..width {
+                let glow = blur_at(|i| rows[(y as isize + i).clamp(0, height as isize - 1) as usize * width + x]);
+                self.glow.push(glow);
+            }
+        }
+    }
+}
+
+// Weighted sum of pixel(-2) to pixel(2)
+fn blur_at(pixel: impl Fn(isize) -> Color) -> Color {
+    let mut sum = (0.0, 0.0, 0.0);
+    for (i, weight) in (-2..=2).zip(BLUR) {
+        let (r, g, b) = pixel(i);
+        sum = (sum.0 + r * weight, sum.1 + g * weight, sum.2 + b * weight);
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgb = (255, 255, 255);
+
+    // A frame where every pixel is a different colour
+    fn frame(width: usize, height: usize) -> Vec<Rgb> {
+        (0..width * height).map(|n| ((n * 7) as u8, (n * 13) as u8, (n * 29 + 1) as u8)).collect()
+    }
+
+    fn pixel(out: &[u8], out_w: usize, x: usize, y: usize) -> Rgb {
+        let n = (y * out_w + x) * 3;
+        (out[n], out[n + 1], out[n + 2])
+    }
+
+    #[test]
+    fn strength_zero_is_plain_scaling() {
+        let colors = frame(8, 4);
+        let mut crt = Crt::default();
+        assert!(!crt.enabled());
+        for (out_w, out_h) in [(8, 4), (32, 16), (13, 7), (5, 3), (100, 61)] {
+            let out = crt.render(&colors, 8, 4, out_w, out_h).to_vec();
+            assert_eq!(out.len(), out_w * out_h * 3);
+            for y in 0..out_h {
+                for x in 0..out_w {
+                    // Nearest neighbour, sampled at the middle of each window pixel
+                    let sx = ((x as f32 + 0.5) * (8.0 / out_w as f32)) as usize;
+                    let sy = ((y as f32 + 0.5) * (4.0 / out_h as f32)) as usize;
+                    assert_eq!(pixel(&out, out_w, x, y), colors[sy * 8 + sx], "at {},{} of {}x{}", x, y, out_w, out_h);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scanlines_darken_the_edges_of_each_row() {
+        let mut crt = Crt { scanlines: 1.0, ..Crt::default() };
+        let out = crt.render(&[WHITE; 2], 1, 2, 1, 8).to_vec();
+        // 4 window rows per CHIP-8 row, 1 - edge² of 0.75 and 0.25 from the middle
+        let rows: Vec<u8> = out.chunks(3).map(|rgb| rgb[0]).collect();
+        assert_eq!(rows, [111, 239, 239, 111, 111, 239, 239, 111]);
+    }
+
+    #[test]
+    fn grid_darkens_the_last_pixel_of_each_cell() {
+        let mut crt = Crt { grid: 1.0, ..Crt::default() };
+        let out = crt.render(&[WHITE; 4], 2, 2, 8, 8).to_vec();
+        for y in 0..8 {
+            for x in 0..8 {
+                let gap = x % 4 == 3 || y % 4 == 3;
+                assert_eq!(pixel(&out, 8, x, y), if gap { (0, 0, 0) } else { WHITE }, "at {},{}", x, y);
+            }
+        }
+        // Below 2 window pixels per CHIP-8 pixel there is no room for gaps
+        assert!(crt.render(&[WHITE; 4], 2, 2, 3, 3).iter().all(|&c| c == 255));
+    }
+
+    #[test]
+    fn bloom_only_lights_darker_surroundings() {
+        let mut crt = Crt { bloom: 1.0, ..Crt::default() };
+        // A flat picture has nothing brighter around it
+        assert!(crt.render(&[(40, 80, 120); 16], 4, 4, 16, 16).chunks(3).all(|rgb| rgb == [40, 80, 120]));
+
+        let mut colors = [(0, 0, 0); 25];
+        colors[12] = WHITE;
+        let out = crt.render(&colors, 5, 5, 5, 5).to_vec();
+        assert_eq!(pixel(&out, 5, 2, 2), WHITE);
+        let next = pixel(&out, 5, 3, 2);
+        let corner = pixel(&out, 5, 4, 4);
+        assert!(next.0 > corner.0 && corner.0 > 0, "{:?} {:?}", next, corner);
+        assert_eq!(pixel(&out, 5, 1, 2), next);
+    }
+
+    #[test]
+    fn curvature_stays_inside_the_image() {
+        let mut crt = Crt { curvature: 1.0, ..Crt::default() };
+        for (out_w, out_h) in [(1, 1), (2, 1), (1, 3), (64, 32), (33, 17)] {
+            let out = crt.render(&[WHITE; 8], 4, 2, out_w, out_h);
+            assert_eq!(out.len(), out_w * out_h * 3);
+        }
+        let (out_w, out_h) = (64, 32);
+        let out = crt.render(&[WHITE; 8], 4, 2, out_w, out_h).to_vec();
+        // The corners curve away
+        for (x, y) in [(0, 0), (out_w - 1, 0), (0, out_h - 1), (out_w - 1, out_h - 1)] {
+            assert_eq!(pixel(&out, out_w, x, y), (0, 0, 0), "corner {},{}", x, y);
+        }
+        // The middle of every edge still shows the picture
+        for (x, y) in [(out_w / 2, 0), (out_w / 2, out_h - 1), (0, out_h / 2), (out_w - 1, out_h / 2)] {
+            assert_eq!(pixel(&out, out_w, x, y), WHITE, "edge {},{}", x, y);
+        }
+        // Nothing but the picture and black
+        assert!(out.chunks(3).all(|rgb| rgb == [255; 3] || rgb == [0; 3]));
+    }
+
+    #[test]
+    fn curvature_keeps_the_middle_in_place() {
+        let colors = frame(8, 4);
+        let flat = Crt::default().render(&colors, 8, 4, 64, 32).to_vec();
+        let mut crt = Crt { curvature: 1.0, ..Crt::default() };
+        let curved = crt.render(&colors, 8, 4, 64, 32).to_vec();
+        assert_eq!(pixel(&curved, 64, 32, 16), pixel(&flat, 64, 32, 16));
+        // Resizing rebuilds the mapping instead of reusing the old one
+        assert_eq!(crt.render(&colors, 8, 4, 16, 8).len(), 16 * 8 * 3);
+    }
+
+    #[test]
+    fn empty_sizes() {
+        let mut crt = Crt { scanlines: 1.0, grid: 1.0, bloom: 1.0, curvature: 1.0, ..Crt::default() };
+        assert!(crt.render(&[], 0, 0, 10, 10).iter().all(|&c| c == 0));
+        assert!(crt.render(&[WHITE], 1, 1, 0, 5).is_empty());
+    }
+}
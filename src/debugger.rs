@@ -0,0 +1,594 @@
+// Interactive debugger. While it has breakpoints or watchpoints it runs the
+// machine one Chip8::cycle at a time instead of a frame at once, so it can
+// stop anywhere in a frame. When stopped, repl() reads commands from stdin.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::chip8::{Chip8, NUM_VREGS};
+use crate::decoder::{decode, Instruction};
+use crate::error::Chip8Error;
+use crate::frontend::{AudioSink, FrameSink, InputProvider};
+use crate::quirks::LoadStoreI;
+
+// Most instructions `next` and `until` run before giving up
+const RUN_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+Addresses and values are hex, counts are decimal.
+  c, continue            resume running
+  s, step [n]            run n instructions (default 1)
+  n, next                step, running CALLs through to their return
+  u, until <addr>        run until PC reaches addr
+  b, break [addr]        set a breakpoint, or list them
+  d, delete [addr]       clear a breakpoint, or all of them
+  w, watch <target>      stop after writes to target: vX, i, or addr [len]
+  unwatch [target]       clear a watchpoint, or all of them
+  r, regs                registers and stack
+  x <addr> [len]         hexdump memory
+  l, list [addr] [n]     disassemble n instructions (default 10 from PC)
+  set <target> <value>   set vX, i or pc, or write bytes from addr
+  q, quit                quit the emulator";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watch {
+    Mem(usize),
+    V(usize),
+    I,
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Mem(addr) => write!(f, "[{:03X}]", addr),
+            Watch::V(x) => write!(f, "V{:X}", x),
+            Watch::I => write!(f, "I"),
+        }
+    }
+}
+
+// What an instruction is about to write
+#[derive(Default)]
+struct Writes {
+    // Bit x set for Vx
+    regs: u16,
+    i: bool,
+    // Start and length
+    mem: Option<(usize, usize)>,
+}
+
+impl Writes {
+    fn regs(&mut self, from: u8, to: u8) {
+        for x in from.min(to)..=from.max(to) {
+            self.regs |= 1 << x;
+        }
+    }
+
+    fn hits(&self, watch: Watch) -> bool {
+        match watch {
+            Watch::V(x) => self.regs & (1 << x) != 0,
+            Watch::I => self.i,
+            Watch::Mem(addr) => self.mem.is_some_and(|(start, len)| addr >= start && addr - start < len),
+        }
+    }
+}
+
+fn writes(c8: &Chip8, instruction: Instruction) -> Writes {
+    let mut w = Writes::default();
+    let i = c8.i() as usize;
+    // Whether Fx55/Fx65 with this x move I
+    let inc_i = |x: u8| match c8.quirks.load_store_i {
+        LoadStoreI::Keep => false,
+        LoadStoreI::AddXPlusOne => true,
+        LoadStoreI::AddX => x > 0,
+    };
+    match instruction {
+        Instruction::LdImm(x, _) | Instruction::AddImm(x, _) | Instruction::LdReg(x, _) |
+        Instruction::Rnd(x, _) | Instruction::LdVxDt(x) | Instruction::LdVxK(x) => w.regs(x, x),
+        Instruction::Or(x, _) | Instruction::And(x, _) | Instruction::Xor(x, _) => {
+            w.regs(x, x);
+            if c8.quirks.vf_reset {
+                w.regs(0xF, 0xF);
+            }
+        }
+        Instruction::Add(x, _) | Instruction::Sub(x, _) | Instruction::Shr(x, _) |
+        Instruction::Subn(x, _) | Instruction::Shl(x, _) => {
+            w.regs(x, x);
+            w.regs(0xF, 0xF);
+        }
+        Instruction::Drw(..) => w.regs(0xF, 0xF),
+        Instruction::Load(x, y) => w.regs(x, y),
+        Instruction::LdVxR(x) => w.regs(0, x),
+        Instruction::LdVxI(x) => {
+            w.regs(0, x);
+            w.i = inc_i(x);
+        }
+        Instruction::Save(x, y) => w.mem = Some((i, x.abs_diff(y) as usize + 1)),
+        Instruction::LdIVx(x) => {
+            w.mem = Some((i, x as usize + 1));
+            w.i = inc_i(x);
+        }
+        Instruction::LdBVx(_) => w.mem = Some((i, 3)),
+        Instruction::LdI(_) | Instruction::LdILong | Instruction::AddIVx(_) |
+        Instruction::LdFVx(_) | Instruction::LdHfVx(_) => w.i = true,
+        _ => {}
+    }
+    w
+}
+
+fn instruction_at(c8: &Chip8, addr: usize) -> Option<Instruction> {
+    let bytes = c8.mem().get(addr..addr.checked_add(2)?)?;
+    decode(((bytes[0] as u16) << 8) | bytes[1] as u16).ok()
+}
+
+fn parse_hex(text: &str) -> Result<usize, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", text))
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    match usize::from_str_radix(digit, 16) {
+        Ok(x) if digit.len() == 1 && x < NUM_VREGS => Some(x),
+        _ => None,
+    }
+}
+
+// The debugger doesn't sound the buzzer while stepping
+struct Silent;
+
+impl AudioSink for Silent {
+    fn set_beeping(&mut self, _on: bool) {}
+}
+
+enum Action {
+    Stay,
+    Resume,
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<usize>,
+    pub watches: BTreeSet<Watch>,
+    // Instructions per frame, so stepping still ticks the timers
+    pub ipf: u32,
+    // Instructions of the current frame left to run after a stop
+    remaining: u32,
+    // Why the machine stopped, for the frontend to pick up
+    stop: Option<String>,
+    // Let the instruction under a breakpoint run when resuming from it
+    skip_break: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // True when frames have to go through run_frame() below
+    pub fn armed(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watches.is_empty() || self.remaining > 0
+    }
+
+    // Stopped part way through a frame
+    pub fn mid_frame(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn take_stop(&mut self) -> Option<String> {
+        self.stop.take()
+    }
+
+    // Called when the machine is let go again, so a breakpoint it is
+    // sitting on doesn't stop it straight away
+    pub fn resume(&mut self) {
+        self.skip_break = true;
+    }
+
+    // Runs one instruction and reports the watchpoints it hit
+    fn step(&mut self, c8: &mut Chip8) -> Result<Option<String>, Chip8Error> {
+        let pc = c8.pc();
+        let w = instruction_at(c8, pc).map(|ins| writes(c8, ins)).unwrap_or_default();
+        c8.cycle()?;
+        self.skip_break = false;
+        // Fx0A runs over and over until a key comes up, it only writes then
+        if c8.pc() == pc {
+            return Ok(None);
+        }
+        let hits: Vec<String> = self.watches.iter().filter(|&&watch| w.hits(watch))
+            .map(|watch| watch.to_string()).collect();
+        if hits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("Watchpoint: {} written at {:03X}", hits.join(", "), pc)))
+    }
+
+    // One instruction as part of the current frame, finishing the frame
+    // (without sound) once all its instructions have run
+    pub fn step_in_frame(&mut self, c8: &mut Chip8) -> Result<Option<String>, Chip8Error> {
+        if self.remaining == 0 {
+            self.remaining = self.ipf.max(1);
+        }
+        let hit = self.step(c8)?;
+        self.remaining -= 1;
+        if self.remaining == 0 || c8.exit_flag {
+            self.remaining = 0;
+            c8.end_frame(&mut Silent);
+        }
+        Ok(hit)
+    }
+
+    // Same as Chip8::run_frame but checks breakpoints and watchpoints after
+    // every instruction. A stop leaves the rest of the frame for later.
+    pub fn run_frame(&mut self, c8: &mut Chip8, ipf: u32, input: &mut dyn InputProvider,
+                     audio: &mut dyn AudioSink) -> Result<bool, Chip8Error> {
+        self.ipf = ipf;
+        if self.remaining == 0 {
+            if input.poll_keys(&mut c8.keys) {
+                return Ok(true);
+            }
+            self.remaining = ipf;
+        }
+        while self.remaining > 0 {
+            if !self.skip_break && self.breakpoints.contains(&c8.pc()) {
+                self.stop = Some(format!("Breakpoint at {:03X}", c8.pc()));
+                return Ok(false);
+            }
+            let hit = self.step(c8)?;
+            self.remaining -= 1;
+            if c8.exit_flag {
+                self.remaining = 0;
+            }
+            if hit.is_some() && self.remaining > 0 {
+                self.stop = hit;
+                return Ok(false);
+            }
+            self.stop = hit;
+        }
+        c8.end_frame(audio);
+        Ok(false)
+    }
+
+    // Runs up to `limit` instructions until `done` says so. Returns why it
+    // stopped early, if it did.
+    fn run_until(&mut self, c8: &mut Chip8, limit: u64, done: &dyn Fn(&Chip8) -> bool) -> Option<String> {
+        for n in 0..limit {
+            if n > 0 && self.breakpoints.contains(&c8.pc()) {
+                return Some(format!("Breakpoint at {:03X}", c8.pc()));
+            }
+            match self.step_in_frame(c8) {
+                Ok(Some(hit)) => return Some(hit),
+                Ok(None) => {}
+                Err(e) => return Some(format!("Fault: {}", e)),
+            }
+            if c8.exit_flag {
+                return Some("Program exited".to_string());
+            }
+            if done(c8) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn print_location(&self, c8: &Chip8) {
+        let pc = c8.pc();
+        match instruction_at(c8, pc) {
+            Some(ins) => println!("{:03X}: {}", pc, ins),
+            None => println!("{:03X}: ???", pc),
+        }
+    }
+
+    fn print_registers(&self, c8: &Chip8) {
+        let v: Vec<String> = c8.v().iter().enumerate().map(|(x, v)| format!("V{:X}={:02X}", x, v)).collect();
+        println!("PC={:03X} I={:03X} DT={:02X} ST={:02X}", c8.pc(), c8.i(), c8.dt(), c8.st());
+        println!("{}", v[..8].join(" "));
+        println!("{}", v[8..].join(" "));
+        let stack: Vec<String> = c8.stack().iter().map(|addr| format!("{:03X}", addr)).collect();
+        println!("Stack ({}): {}", stack.len(), stack.join(" "));
+    }
+
+    fn hexdump(&self, c8: &Chip8, start: usize, len: usize) {
+        let mem = c8.mem();
+        let end = start.saturating_add(len).min(mem.len());
+        for row in (start..end).step_by(16) {
+            let bytes: Vec<String> = mem[row..(row + 16).min(end)].iter().map(|b| format!("{:02X}", b)).collect();
+            println!("{:04X}: {}", row, bytes.join(" "));
+        }
+    }
+
+    fn parse_watches(&self, c8: &Chip8, args: &[&str]) -> Result<Vec<Watch>, String> {
+        let (addr, len) = match args {
+            [] => return Err("missing watch target".to_string()),
+            [target] if target.eq_ignore_ascii_case("i") => return Ok(vec![Watch::I]),
+            [target] if parse_register(target).is_some() => return Ok(vec![Watch::V(parse_register(target).unwrap())]),
+            [addr] => (parse_hex(addr)?, 1),
+            [addr, len, ..] => (parse_hex(addr)?, parse_hex(len)?),
+        };
+        match addr.checked_add(len) {
+            Some(end) if end <= c8.mem().len() => Ok((addr..end).map(Watch::Mem).collect()),
+            _ => Err(format!("{:X} bytes at {:X} go past the end of memory", len, addr)),
+        }
+    }
+
+    fn command(&mut self, c8: &mut Chip8, line: &str) -> Result<Action, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Ok(Action::Stay),
+        };
+        let addr_arg = |n: usize| args.get(n).ok_or_else(|| "missing address".to_string()).and_then(|a| parse_hex(a));
+        match name {
+            "c" | "continue" => return Ok(Action::Resume),
+            "q" | "quit" => return Ok(Action::Quit),
+            "h" | "help" | "?" => println!("{}", HELP),
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(n) => n.parse().map_err(|_| format!("'{}' is not a count", n))?,
+                    None => 1,
+                };
+                if let Some(reason) = self.run_until(c8, count, &|_| false) {
+                    println!("{}", reason);
+                }
+                self.print_location(c8);
+            }
+            "n" | "next" => {
+                let pc = c8.pc();
+                if let Some(Instruction::Call(_)) = instruction_at(c8, pc) {
+                    let depth = c8.stack().len();
+                    let done = move |c8: &Chip8| c8.pc() == pc + 2 && c8.stack().len() == depth;
+                    match self.run_until(c8, RUN_LIMIT, &done) {
+                        Some(reason) => println!("{}", reason),
+                        None if !done(c8) => println!("Gave up after {} instructions", RUN_LIMIT),
+                        None => {}
+                    }
+                } else if let Some(reason) = self.run_until(c8, 1, &|_| false) {
+                    println!("{}", reason);
+                }
+                self.print_location(c8);
+            }
+            "u" | "until" => {
+                let target = addr_arg(0)?;
+                let done = move |c8: &Chip8| c8.pc() == target;
+                match self.run_until(c8, RUN_LIMIT, &done) {
+                    Some(reason) => println!("{}", reason),
+                    None if !done(c8) => println!("Gave up after {} instructions", RUN_LIMIT),
+                    None => {}
+                }
+                self.print_location(c8);
+            }
+            "b" | "break" if args.is_empty() => {
+                let list: Vec<String> = self.breakpoints.iter().map(|a| format!("{:03X}", a)).collect();
+                println!("Breakpoints: {}", list.join(" "));
+            }
+            "b" | "break" => {
+                self.breakpoints.insert(addr_arg(0)?);
+            }
+            "d" | "delete" if args.is_empty() => self.breakpoints.clear(),
+            "d" | "delete" => {
+                if !self.breakpoints.remove(&addr_arg(0)?) {
+                    return Err("no breakpoint there".to_string());
+                }
+            }
+            "w" | "watch" if args.is_empty() => {
+                let list: Vec<String> = self.watches.iter().map(|w| w.to_string()).collect();
+                println!("Watchpoints: {}", list.join(" "));
+            }
+            "w" | "watch" => self.watches.extend(self.parse_watches(c8, args)?),
+            "unwatch" if args.is_empty() => self.watches.clear(),
+            "unwatch" => {
+                for watch in self.parse_watches(c8, args)? {
+                    self.watches.remove(&watch);
+                }
+            }
+            "r" | "regs" => self.print_registers(c8),
+            "x" => {
+                let len = match args.get(1) {
+                    Some(len) => parse_hex(len)?,
+                    None => 0x40,
+                };
+                let addr = addr_arg(0)?;
+                if addr >= c8.mem().len() {
+                    return Err(format!("{:X} is past the end of memory", addr));
+                }
+                self.hexdump(c8, addr, len);
+            }
+            "l" | "list" => {
+                let mut addr = match args.first() {
+                    Some(a) => parse_hex(a)?,
+                    None => c8.pc(),
+                };
+                let count = match args.get(1) {
+                    Some(n) => n.parse().map_err(|_| format!("'{}' is not a count", n))?,
+                    None => 10,
+                };
+                for _ in 0..count {
+                    let Some(bytes) = addr.checked_add(2).and_then(|end| c8.mem().get(addr..end)) else { break };
+                    let marker = if addr == c8.pc() { ">" } else if self.breakpoints.contains(&addr) { "*" } else { " " };
+                    match instruction_at(c8, addr) {
+                        Some(ins) => println!("{}{:03X}: {:02X}{:02X}  {}", marker, addr, bytes[0], bytes[1], ins),
+                        None => println!("{}{:03X}: {:02X}{:02X}  ???", marker, addr, bytes[0], bytes[1]),
+                    }
+                    addr += instruction_at(c8, addr).map_or(2, |ins| ins.size());
+                }
+            }
+            "set" => {
+                let (target, values) = match args.split_first() {
+                    Some((target, values)) if !values.is_empty() => (*target, values),
+                    _ => return Err("usage: set <vX|i|pc|addr> <value...>".to_string()),
+                };
+                let value = parse_hex(values[0])?;
+                if let Some(x) = parse_register(target) {
+                    c8.set_v(x, value as u8);
+                } else if target.eq_ignore_ascii_case("i") {
+                    c8.set_i(value as u16);
+                } else if target.eq_ignore_ascii_case("pc") {
+                    c8.set_pc(value);
+                } else {
+                    let addr = parse_hex(target)?;
+                    for (n, byte) in values.iter().enumerate() {
+                        let byte = parse_hex(byte)? as u8;
+                        match c8.mem_mut().get_mut(addr + n) {
+                            Some(b) => *b = byte,
+                            None => return Err(format!("{:X} is outside memory", addr + n)),
+                        }
+                    }
+                    c8.draw_flag = true;
+                }
+            }
+            _ => return Err(format!("unknown command '{}', try 'help'", name)),
+        }
+        Ok(Action::Stay)
+    }
+
+    // Takes commands from stdin until one resumes the machine. The screen is
+    // redrawn after commands that change it. Returns true to quit.
+    pub fn repl(&mut self, c8: &mut Chip8, ipf: u32, screen: &mut dyn FrameSink) -> bool {
+        self.ipf = ipf;
+        self.print_location(c8);
+        let stdin = io::stdin();
+        loop {
+            print!("(chip8) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return true;
+            }
+            // An empty line repeats the last command, handy for stepping
+            let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+            let action = self.command(c8, &line);
+            self.last_command = line;
+            match action {
+                Ok(Action::Stay) => {}
+                Ok(Action::Resume) => {
+                    self.resume();
+                    return false;
+                }
+                Ok(Action::Quit) => return true,
+                Err(message) => println!("{}", message),
+            }
+            if c8.draw_flag {
+                c8.draw_flag = false;
+                screen.present(c8.screen(), c8.disp_x(), c8.disp_y());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::NUM_KEYS;
+    use crate::quirks::Quirks;
+
+    struct NoKeys;
+
+    impl InputProvider for NoKeys {
+        fn poll_keys(&mut self, _keys: &mut [bool; NUM_KEYS]) -> bool {
+            false
+        }
+    }
+
+    fn machine(program: &[u16]) -> Chip8 {
+        let mut c8 = Chip8::new();
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        c8.load_rom_bytes(&rom).unwrap();
+        c8
+    }
+
+    // Watches the instruction at 0x200 would trigger
+    fn hit(c8: &Chip8, watches: &[Watch]) -> Vec<Watch> {
+        let w = writes(c8, instruction_at(c8, 0x200).unwrap());
+        watches.iter().copied().filter(|&watch| w.hits(watch)).collect()
+    }
+
+    #[test]
+    fn writes_of_each_instruction() {
+        let all = [Watch::V(0), Watch::V(3), Watch::V(0xF), Watch::I, Watch::Mem(0x2FF), Watch::Mem(0x300), Watch::Mem(0x303)];
+        let mut c8 = machine(&[0x6300]);
+        c8.set_i(0x300);
+        let cases: [(u16, &[Watch]); 12] = [
+            (0x6300, &[Watch::V(3)]),
+            (0x8304, &[Watch::V(3), Watch::V(0xF)]),
+            (0x8301, &[Watch::V(3)]),
+            (0xD011, &[Watch::V(0xF)]),
+            (0x5303, &[Watch::V(0), Watch::V(3)]),
+            (0xF385, &[Watch::V(0), Watch::V(3)]),
+            (0xF365, &[Watch::V(0), Watch::V(3)]),
+            (0xF355, &[Watch::Mem(0x300), Watch::Mem(0x303)]),
+            (0xF255, &[Watch::Mem(0x300)]),
+            (0xF033, &[Watch::Mem(0x300)]),
+            (0xA123, &[Watch::I]),
+            (0x1200, &[]),
+        ];
+        for (opcode, expected) in cases {
+            c8.mem_mut()[0x200..0x202].copy_from_slice(&opcode.to_be_bytes());
+            assert_eq!(hit(&c8, &all), expected, "{:04X}", opcode);
+        }
+
+        // Quirks decide whether VF and I are written
+        c8.quirks = Quirks::COSMAC_VIP;
+        c8.mem_mut()[0x200..0x202].copy_from_slice(&0x8301u16.to_be_bytes());
+        assert_eq!(hit(&c8, &all), [Watch::V(3), Watch::V(0xF)]);
+        c8.mem_mut()[0x200..0x202].copy_from_slice(&0xF355u16.to_be_bytes());
+        assert_eq!(hit(&c8, &all), [Watch::I, Watch::Mem(0x300), Watch::Mem(0x303)]);
+        // CHIP-48 adds x, which for V0 alone leaves I where it was
+        c8.quirks = Quirks::CHIP_48;
+        c8.mem_mut()[0x200..0x202].copy_from_slice(&0xF055u16.to_be_bytes());
+        assert_eq!(hit(&c8, &all), [Watch::Mem(0x300)]);
+    }
+
+    #[test]
+    fn watch_targets_must_fit_in_memory() {
+        let debugger = Debugger::new();
+        let c8 = Chip8::new();
+        let parse = |args: &[&str]| debugger.parse_watches(&c8, args);
+        assert_eq!(parse(&["v3"]), Ok(vec![Watch::V(3)]));
+        assert_eq!(parse(&["I"]), Ok(vec![Watch::I]));
+        assert_eq!(parse(&["300"]), Ok(vec![Watch::Mem(0x300)]));
+        assert_eq!(parse(&["0x300", "3"]), Ok(vec![Watch::Mem(0x300), Watch::Mem(0x301), Watch::Mem(0x302)]));
+        // Right up to the last byte
+        assert_eq!(parse(&["FFF"]), Ok(vec![Watch::Mem(0xFFF)]));
+        assert_eq!(parse(&["FFC", "4"]).map(|w| w.len()), Ok(4));
+        assert_eq!(parse(&["FFC", "5"]), Err("5 bytes at FFC go past the end of memory".to_string()));
+        assert!(parse(&["1000"]).is_err());
+        assert!(parse(&["1", "FFFFFFFFFFFFFFFF"]).is_err());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["vg"]).is_err());
+    }
+
+    #[test]
+    fn commands_reject_addresses_past_memory() {
+        let mut debugger = Debugger::new();
+        let mut c8 = Chip8::new();
+        assert!(debugger.command(&mut c8, "x FFF 10").is_ok());
+        assert!(debugger.command(&mut c8, "x 1000").is_err());
+        assert!(debugger.command(&mut c8, "x FFFFFFFFFFFFFFFF FFFFFFFFFFFFFFFF").is_err());
+        assert!(debugger.command(&mut c8, "l FFFFFFFFFFFFFFFF 4").is_ok());
+        assert!(debugger.command(&mut c8, "set FFF 1").is_ok());
+        assert!(debugger.command(&mut c8, "set FFF 1 2").is_err());
+        assert_eq!(c8.mem()[0xFFF], 1);
+        assert!(debugger.command(&mut c8, "w FFE 2").is_ok());
+        assert!(debugger.command(&mut c8, "w FFE 3").is_err());
+        assert_eq!(debugger.watches.len(), 2);
+    }
+
+    #[test]
+    fn watchpoints_stop_mid_frame() {
+        // LD I, 0xFFE, LD V0, 1, LD [I], V1 writes the last two bytes
+        let mut c8 = machine(&[0xAFFE, 0x6001, 0xF155, 0x1206]);
+        let mut debugger = Debugger::new();
+        debugger.watches.insert(Watch::Mem(0xFFF));
+        assert!(debugger.armed());
+        assert!(!debugger.run_frame(&mut c8, 10, &mut NoKeys, &mut Silent).unwrap());
+        assert_eq!(debugger.take_stop(), Some("Watchpoint: [FFF] written at 204".to_string()));
+        assert_eq!(c8.pc(), 0x206);
+        assert!(debugger.mid_frame());
+        // The rest of the frame runs from there
+        assert!(!debugger.run_frame(&mut c8, 10, &mut NoKeys, &mut Silent).unwrap());
+        assert_eq!(debugger.take_stop(), None);
+        assert!(!debugger.mid_frame());
+    }
+}
@@ -0,0 +1,373 @@
+// Turns opcodes into Instructions. This is the one place that knows the
+// CHIP-8 encoding, so the interpreter, disassembler, debugger and tracer all
+// agree on what a given opcode means. Display prints the classic (Cowgod)
+// assembler syntax, e.g. "LD V0, 0x0C" or "DRW V0, V1, 0xF".
+//
+// The decoder accepts the union of CHIP-8, HIRES CHIP-8, SUPER-CHIP and
+// XO-CHIP. SUPER-CHIP is always available; it is up to the interpreter to
+// reject XO-CHIP instructions when those are off, and to run 0230 as the
+// SYS call it is outside HIRES. Any other 0nnn is a SYS call.
+use std::fmt;
+
+// Register index (0x0 - 0xF)
+pub type Reg = u8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    // 0nnn: call a machine code routine of the original interpreter's CPU
+    Sys(u16),
+    // 00Cn: scroll down n lines (SUPER-CHIP)
+    Scd(u8),
+    // 00Dn: scroll up n lines (XO-CHIP)
+    Scu(u8),
+    // 00E0
+    Cls,
+    // 0230: clear the 64x64 screen (HIRES CHIP-8)
+    ClsHires,
+    // 00EE
+    Ret,
+    // 00FB: scroll right 4 pixels (SUPER-CHIP)
+    Scr,
+    // 00FC: scroll left 4 pixels (SUPER-CHIP)
+    Scl,
+    // 00FD: exit the interpreter (SUPER-CHIP)
+    Exit,
+    // 00FE: 64x32 mode (SUPER-CHIP)
+    Low,
+    // 00FF: 128x64 mode (SUPER-CHIP)
+    High,
+    // 1nnn
+    Jp(u16),
+    // 2nnn
+    Call(u16),
+    // 3xkk: skip if Vx == kk
+    SeImm(Reg, u8),
+    // 4xkk: skip if Vx != kk
+    SneImm(Reg, u8),
+    // 5xy0: skip if Vx == Vy
+    SeReg(Reg, Reg),
+    // 5xy2: store Vx..Vy at I (XO-CHIP)
+    Save(Reg, Reg),
+    // 5xy3: load Vx..Vy from I (XO-CHIP)
+    Load(Reg, Reg),
+    // 6xkk
+    LdImm(Reg, u8),
+    // 7xkk
+    AddImm(Reg, u8),
+    // 8xy0
+    LdReg(Reg, Reg),
+    // 8xy1
+    Or(Reg, Reg),
+    // 8xy2
+    And(Reg, Reg),
+    // 8xy3
+    Xor(Reg, Reg),
+    // 8xy4
+    Add(Reg, Reg),
+    // 8xy5
+    Sub(Reg, Reg),
+    // 8xy6
+    Shr(Reg, Reg),
+    // 8xy7
+    Subn(Reg, Reg),
+    // 8xyE
+    Shl(Reg, Reg),
+    // 9xy0: skip if Vx != Vy
+    SneReg(Reg, Reg),
+    // Annn
+    LdI(u16),
+    // Bnnn: jump to nnn + V0 (or xnn + Vx, depending on quirks)
+    JpV0(u16),
+    // Cxkk
+    Rnd(Reg, u8),
+    // Dxyn, n = 0 draws a 16x16 sprite
+    Drw(Reg, Reg, u8),
+    // Ex9E
+    Skp(Reg),
+    // ExA1
+    Sknp(Reg),
+    // F000 nnnn: the 16 bit address is the word after the opcode (XO-CHIP)
+    LdILong,
+    // Fn01: select bitplanes (XO-CHIP)
+    Plane(u8),
+    // Fx07
+    LdVxDt(Reg),
+    // Fx0A
+    LdVxK(Reg),
+    // Fx15
+    LdDtVx(Reg),
+    // Fx18
+    LdStVx(Reg),
+    // Fx1E
+    AddIVx(Reg),
+    // Fx29
+    LdFVx(Reg),
+    // Fx30: big font digit (SUPER-CHIP)
+    LdHfVx(Reg),
+    // Fx33
+    LdBVx(Reg),
+    // Fx55
+    LdIVx(Reg),
+    // Fx65
+    LdVxI(Reg),
+    // Fx75: save to RPL flags (SUPER-CHIP)
+    LdRVx(Reg),
+    // Fx85: load from RPL flags (SUPER-CHIP)
+    LdVxR(Reg),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid opcode {:#06X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    use Instruction::*;
+    let x = ((opcode & 0x0F00) >> 8) as Reg;
+    let y = ((opcode & 0x00F0) >> 4) as Reg;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    let n = (opcode & 0x000F) as u8;
+
+    let instruction = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00C0..=0x00CF => Scd(n),
+            0x00D0..=0x00DF => Scu(n),
+            0x00E0 => Cls,
+            0x0230 => ClsHires,
+            0x00EE => Ret,
+            0x00FB => Scr,
+            0x00FC => Scl,
+            0x00FD => Exit,
+            0x00FE => Low,
+            0x00FF => High,
+            _ => Sys(nnn),
+        },
+        0x1000 => Jp(nnn),
+        0x2000 => Call(nnn),
+        0x3000 => SeImm(x, kk),
+        0x4000 => SneImm(x, kk),
+        0x5000 => match n {
+            0x0 => SeReg(x, y),
+            0x2 => Save(x, y),
+            0x3 => Load(x, y),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6000 => LdImm(x, kk),
+        0x7000 => AddImm(x, kk),
+        0x8000 => match n {
+            0x0 => LdReg(x, y),
+            0x1 => Or(x, y),
+            0x2 => And(x, y),
+            0x3 => Xor(x, y),
+            0x4 => Add(x, y),
+            0x5 => Sub(x, y),
+            0x6 => Shr(x, y),
+            0x7 => Subn(x, y),
+            0xE => Shl(x, y),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9000 if n == 0 => SneReg(x, y),
+        0xA000 => LdI(nnn),
+        0xB000 => JpV0(nnn),
+        0xC000 => Rnd(x, kk),
+        0xD000 => Drw(x, y, n),
+        0xE000 => match kk {
+            0x9E => Skp(x),
+            0xA1 => Sknp(x),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xF000 => match kk {
+            0x00 if x == 0 => LdILong,
+            0x01 => Plane(x),
+            0x07 => LdVxDt(x),
+            0x0A => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1E => AddIVx(x),
+            0x29 => LdFVx(x),
+            0x30 => LdHfVx(x),
+            0x33 => LdBVx(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            0x75 => LdRVx(x),
+            0x85 => LdVxR(x),
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    };
+    Ok(instruction)
+}
+
+impl Instruction {
+    // Size in bytes, including the operand word of the XO-CHIP long I load
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    // True for instructions that only exist in HIRES CHIP-8
+    pub fn is_hires(&self) -> bool {
+        matches!(self, Instruction::ClsHires)
+    }
+
+    // True for instructions that only exist in XO-CHIP
+    pub fn is_xo_chip(&self) -> bool {
+        use Instruction::*;
+        matches!(self, Scu(_) | Save(..) | Load(..) | LdILong | Plane(_))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Scd(n) => write!(f, "SCD {:#X}", n),
+            Scu(n) => write!(f, "SCU {:#X}", n),
+            Cls | ClsHires => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            SeImm(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            SneImm(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Save(x, y) => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Load(x, y) => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            LdImm(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            AddImm(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Rnd(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:#X}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {:#X}", n),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn every_family_decodes_and_prints() {
+        let table = [
+            (0x0123, Sys(0x123), "SYS 0x123"),
+            (0x00C4, Scd(4), "SCD 0x4"),
+            (0x00DA, Scu(0xA), "SCU 0xA"),
+            (0x00E0, Cls, "CLS"),
+            (0x0230, ClsHires, "CLS"),
+            (0x00EE, Ret, "RET"),
+            (0x00FB, Scr, "SCR"),
+            (0x00FC, Scl, "SCL"),
+            (0x00FD, Exit, "EXIT"),
+            (0x00FE, Low, "LOW"),
+            (0x00FF, High, "HIGH"),
+            (0x1ABC, Jp(0xABC), "JP 0xABC"),
+            (0x2ABC, Call(0xABC), "CALL 0xABC"),
+            (0x31FF, SeImm(1, 0xFF), "SE V1, 0xFF"),
+            (0x4E07, SneImm(0xE, 0x07), "SNE VE, 0x07"),
+            (0x5120, SeReg(1, 2), "SE V1, V2"),
+            (0x5A32, Save(0xA, 3), "SAVE VA - V3"),
+            (0x5013, Load(0, 1), "LOAD V0 - V1"),
+            (0x6C0C, LdImm(0xC, 0x0C), "LD VC, 0x0C"),
+            (0x7F80, AddImm(0xF, 0x80), "ADD VF, 0x80"),
+            (0x8120, LdReg(1, 2), "LD V1, V2"),
+            (0x8121, Or(1, 2), "OR V1, V2"),
+            (0x8122, And(1, 2), "AND V1, V2"),
+            (0x8123, Xor(1, 2), "XOR V1, V2"),
+            (0x8124, Add(1, 2), "ADD V1, V2"),
+            (0x8125, Sub(1, 2), "SUB V1, V2"),
+            (0x8126, Shr(1, 2), "SHR V1, V2"),
+            (0x8127, Subn(1, 2), "SUBN V1, V2"),
+            (0x812E, Shl(1, 2), "SHL V1, V2"),
+            (0x9AB0, SneReg(0xA, 0xB), "SNE VA, VB"),
+            (0xA22A, LdI(0x22A), "LD I, 0x22A"),
+            (0xB300, JpV0(0x300), "JP V0, 0x300"),
+            (0xC50F, Rnd(5, 0x0F), "RND V5, 0x0F"),
+            (0xD01F, Drw(0, 1, 0xF), "DRW V0, V1, 0xF"),
+            (0xD120, Drw(1, 2, 0), "DRW V1, V2, 0x0"),
+            (0xE39E, Skp(3), "SKP V3"),
+            (0xE3A1, Sknp(3), "SKNP V3"),
+            (0xF000, LdILong, "LD I, LONG"),
+            (0xF201, Plane(2), "PLANE 0x2"),
+            (0xF407, LdVxDt(4), "LD V4, DT"),
+            (0xF40A, LdVxK(4), "LD V4, K"),
+            (0xF415, LdDtVx(4), "LD DT, V4"),
+            (0xF418, LdStVx(4), "LD ST, V4"),
+            (0xF41E, AddIVx(4), "ADD I, V4"),
+            (0xF429, LdFVx(4), "LD F, V4"),
+            (0xF430, LdHfVx(4), "LD HF, V4"),
+            (0xF433, LdBVx(4), "LD B, V4"),
+            (0xF455, LdIVx(4), "LD [I], V4"),
+            (0xF465, LdVxI(4), "LD V4, [I]"),
+            (0xF475, LdRVx(4), "LD R, V4"),
+            (0xF485, LdVxR(4), "LD V4, R"),
+        ];
+        for (opcode, instruction, mnemonic) in table {
+            assert_eq!(decode(opcode), Ok(instruction), "{:04X}", opcode);
+            assert_eq!(instruction.to_string(), mnemonic);
+        }
+        assert_eq!(LdILong.size(), 4);
+        assert_eq!(Cls.size(), 2);
+        for instruction in [Scu(1), Save(0, 1), Load(0, 1), LdILong, Plane(3)] {
+            assert!(instruction.is_xo_chip());
+        }
+        assert!(!Scd(1).is_xo_chip() && !High.is_xo_chip());
+        assert!(ClsHires.is_hires() && !Cls.is_hires());
+    }
+
+    #[test]
+    fn invalid_encodings_are_errors() {
+        for opcode in [0x5121, 0x5124, 0x512F, 0x8008, 0x800D, 0x800F, 0x9001, 0x900E,
+                       0xE09F, 0xE0A2, 0xE000, 0xF100, 0xF002, 0xF0FF, 0xFFFF] {
+            assert_eq!(decode(opcode), Err(DecodeError { opcode }), "{:04X}", opcode);
+        }
+        assert_eq!(DecodeError { opcode: 0x5121 }.to_string(), "invalid opcode 0x5121");
+
+        // Everything else decodes: the gaps in 5xyn, 8xyn, 9xyn, Exkk and Fxkk
+        let invalid = (0..=0xFFFF).filter(|&opcode| decode(opcode).is_err()).count();
+        assert_eq!(invalid, 13 * 256 + 7 * 256 + 15 * 256 + 16 * 254 + (16 * 256 - 13 * 16 - 1));
+    }
+}
@@ -0,0 +1,263 @@
+// Static disassembly of a ROM. Starting from the entry point we follow
+// jumps, calls and both sides of every skip (recursive descent), so anything
+// reachable is code and everything else is data, which is usually sprites.
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::chip8::PROGRAM_START;
+use crate::decoder::{decode, Instruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    // Target of a CALL
+    Subroutine,
+    // Target of a JP
+    Jump,
+    // Address loaded into I
+    Data,
+}
+
+pub struct Disassembly {
+    rom: Vec<u8>,
+    // Decoded instructions by address
+    pub code: BTreeMap<usize, Instruction>,
+    pub labels: BTreeMap<usize, LabelKind>,
+    // Operands of the XO-CHIP long I loads, by address of the F000
+    long_i: BTreeMap<usize, u16>,
+}
+
+impl Disassembly {
+    // Analyses a ROM loaded at 0x200. `xo_chip` makes F000 nnnn a four byte
+    // instruction instead of an invalid opcode.
+    pub fn new(rom: &[u8], xo_chip: bool) -> Self {
+        let mut dis = Self {
+            rom: rom.to_vec(),
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            long_i: BTreeMap::new(),
+        };
+        // HIRES CHIP-8 programs jump over 1802 machine code to 0x2C0
+        let entry = if rom.starts_with(&[0x12, 0x60]) {
+            dis.code.insert(PROGRAM_START, Instruction::Jp(0x260));
+            0x2C0
+        } else {
+            PROGRAM_START
+        };
+        dis.trace(entry, xo_chip);
+        dis
+    }
+
+    fn end(&self) -> usize {
+        PROGRAM_START + self.rom.len()
+    }
+
+    fn word(&self, addr: usize) -> Option<u16> {
+        if addr < PROGRAM_START || addr + 2 > self.end() {
+            return None;
+        }
+        let offset = addr - PROGRAM_START;
+        Some(((self.rom[offset] as u16) << 8) | self.rom[offset + 1] as u16)
+    }
+
+    fn decode_at(&self, addr: usize, xo_chip: bool) -> Option<Instruction> {
+        let instruction = decode(self.word(addr)?).ok()?;
+        if instruction.is_xo_chip() && !xo_chip {
+            return None;
+        }
+        Some(instruction)
+    }
+
+    fn label(&mut self, addr: usize, kind: LabelKind) {
+        let entry = self.labels.entry(addr).or_insert(kind);
+        // A subroutine that is also jumped to is still a subroutine
+        if kind < *entry {
+            *entry = kind;
+        }
+    }
+
+    fn trace(&mut self, entry: usize, xo_chip: bool) {
+        let mut pending = vec![entry];
+        let mut seen = BTreeSet::new();
+        while let Some(addr) = pending.pop() {
+            if !seen.insert(addr) {
+                continue;
+            }
+            let instruction = match self.decode_at(addr, xo_chip) {
+                Some(instruction) => instruction,
+                // Ran into something that is not code, stop this path
+                None => continue,
+            };
+            if instruction == Instruction::LdILong {
+                match self.word(addr + 2) {
+                    Some(long) => {
+                        self.long_i.insert(addr, long);
+                        self.label(long as usize, LabelKind::Data);
+                    }
+                    None => continue,
+                }
+            }
+            self.code.insert(addr, instruction);
+
+            let next = addr + instruction.size();
+            match instruction {
+                Instruction::Jp(nnn) => {
+                    self.label(nnn as usize, LabelKind::Jump);
+                    pending.push(nnn as usize);
+                }
+                Instruction::Call(nnn) => {
+                    self.label(nnn as usize, LabelKind::Subroutine);
+                    pending.push(nnn as usize);
+                    pending.push(next);
+                }
+                // Computed jump, usually into a table of JPs. V0 = 0 is the
+                // best guess we can make statically.
+                Instruction::JpV0(nnn) => {
+                    self.label(nnn as usize, LabelKind::Jump);
+                    pending.push(nnn as usize);
+                }
+                Instruction::Ret | Instruction::Exit => {}
+                Instruction::SeImm(..) | Instruction::SneImm(..) | Instruction::SeReg(..) |
+                Instruction::SneReg(..) | Instruction::Skp(_) | Instruction::Sknp(_) => {
+                    pending.push(next);
+                    let skipped = self.decode_at(next, xo_chip).map_or(2, |i| i.size());
+                    pending.push(next + skipped);
+                }
+                Instruction::LdI(nnn) => {
+                    self.label(nnn as usize, LabelKind::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    pub fn label_name(&self, addr: usize) -> Option<String> {
+        self.labels.get(&addr).map(|kind| match kind {
+            LabelKind::Subroutine => format!("sub_{:03X}", addr),
+            LabelKind::Jump => format!("label_{:03X}", addr),
+            LabelKind::Data => format!("data_{:03X}", addr),
+        })
+    }
+
+    // An address operand, by label if there is one
+    fn target(&self, addr: u16) -> String {
+        self.label_name(addr as usize).unwrap_or_else(|| format!("{:#05X}", addr))
+    }
+
+    // Formats an instruction with its address operands replaced by labels
+    pub fn format(&self, addr: usize, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::Jp(nnn) => format!("JP {}", self.target(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", self.target(nnn)),
+            Instruction::JpV0(nnn) => format!("JP V0, {}", self.target(nnn)),
+            Instruction::LdI(nnn) => format!("LD I, {}", self.target(nnn)),
+            Instruction::LdILong => match self.long_i.get(&addr) {
+                Some(&long) => format!("LD I, LONG {}", self.target(long)),
+                None => instruction.to_string(),
+            },
+            _ => instruction.to_string(),
+        }
+    }
+
+    // Writes the listing: code with labels, data as hex and sprite bitmaps
+    pub fn write_listing(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut addr = PROGRAM_START;
+        let mut in_data = false;
+        while addr < self.end() {
+            if let Some(name) = self.label_name(addr) {
+                writeln!(out)?;
+                writeln!(out, "{}:", name)?;
+            }
+            if let Some(&instruction) = self.code.get(&addr) {
+                in_data = false;
+                let size = instruction.size().min(self.end() - addr);
+                let raw: Vec<String> = self.rom[addr - PROGRAM_START..addr - PROGRAM_START + size]
+                    .iter().map(|b| format!("{:02X}", b)).collect();
+                writeln!(out, "    {:03X}: {:<12} {}", addr, raw.join(""), self.format(addr, instruction))?;
+                addr += size;
+            } else {
+                if !in_data {
+                    writeln!(out, "    ; data")?;
+                    in_data = true;
+                }
+                let byte = self.rom[addr - PROGRAM_START];
+                let bitmap: String = (0..8).rev()
+                    .map(|bit| if byte & (1 << bit) != 0 { '#' } else { '.' })
+                    .collect();
+                writeln!(out, "    {:03X}: {:02X}           {}", addr, byte, bitmap)?;
+                addr += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn listing(dis: &Disassembly) -> String {
+        let mut out = Vec::new();
+        dis.write_listing(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn separates_code_from_data() {
+        let dis = Disassembly::new(&rom(&[
+            0xA210, // 200: LD I, sprite
+            0x3005, // 202: SE V0, 5
+            0x2212, // 204: CALL sub
+            0xE19E, // 206: SKP V1
+            0x6001, // 208: LD V0, 1
+            0x120E, // 20A: JP over the data
+            0xFFFF, // 20C: data, jumped over
+            0x120E, // 20E: JP to itself
+            0x3C42, // 210: sprite
+            0x00EE, // 212: RET
+            0x1234, // 214: after the RET, never reached
+        ]), false);
+        let code: Vec<usize> = dis.code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20E, 0x212]);
+        let labels: Vec<(usize, LabelKind)> = dis.labels.iter().map(|(&addr, &kind)| (addr, kind)).collect();
+        assert_eq!(labels, [(0x20E, LabelKind::Jump), (0x210, LabelKind::Data), (0x212, LabelKind::Subroutine)]);
+
+        let text = listing(&dis);
+        for line in ["    200: A210         LD I, data_210",
+                     "    204: 2212         CALL sub_212",
+                     "    20A: 120E         JP label_20E",
+                     "    20C: FF           ########",
+                     "data_210:\n    ; data\n    210: 3C           ..####..\n    211: 42           .#....#.",
+                     "sub_212:\n    212: 00EE         RET\n    ; data\n    214: 12           ...#..#.\n    215: 34           ..##.#.."] {
+            assert!(text.contains(line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn skips_step_over_long_i_loads() {
+        let dis = Disassembly::new(&rom(&[
+            0x3000, // 200: SE V0, 0
+            0xF000, 0x0208, // 202: LD I, LONG data
+            0x1206, // 206: JP to itself
+            0xAA55, // 208: data
+        ]), true);
+        let code: Vec<usize> = dis.code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x206]);
+        assert_eq!(dis.labels.get(&0x208), Some(&LabelKind::Data));
+        assert!(listing(&dis).contains("    202: F0000208     LD I, LONG data_208"));
+    }
+
+    #[test]
+    fn hires_programs_start_at_0x2c0() {
+        let mut program = rom(&[0x1260]);
+        program.resize(0xC0, 0xFF);
+        program.extend(rom(&[0x00E0, 0x12C2]));
+        let dis = Disassembly::new(&program, false);
+        let code: Vec<usize> = dis.code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x2C0, 0x2C2]);
+    }
+}
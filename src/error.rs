@@ -0,0 +1,76 @@
+use std::fmt;
+
+// Everything that can go wrong while loading or running a ROM
+#[derive(Debug)]
+pub enum Chip8Error {
+    // 00EE with nothing on the stack
+    StackUnderflow { pc: usize },
+    // 2nnn with the stack already full
+    StackOverflow { pc: usize },
+    // An instruction (or the fetch itself) touched memory past the end
+    MemoryOutOfBounds { pc: usize, addr: usize },
+    // Opcode that does not decode to anything in the enabled instruction sets
+    InvalidOpcode { pc: usize, opcode: u16 },
+    // ROM does not fit between 0x200 and the end of memory
+    RomTooLarge { size: usize, max: usize },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::StackUnderflow { pc } =>
+                write!(f, "stack underflow (RET with an empty stack) at {:#05X}", pc),
+            Chip8Error::StackOverflow { pc } =>
+                write!(f, "stack overflow at {:#05X}", pc),
+            Chip8Error::MemoryOutOfBounds { pc, addr } =>
+                write!(f, "memory access out of bounds ({:#06X}) at {:#05X}", addr, pc),
+            Chip8Error::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode {:#06X} at {:#05X}", opcode, pc),
+            Chip8Error::RomTooLarge { size, max } =>
+                write!(f, "ROM is {} bytes but only {} fit in memory", size, max),
+            Chip8Error::Io(e) =>
+                write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Chip8Error {
+    fn from(e: std::io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
+
+// What the machine does when an instruction faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    // Stop emulation and report the error
+    #[default]
+    Halt,
+    // Pause with the PC on the faulting instruction so it can be inspected
+    Trap,
+    // Log the error, skip the instruction and carry on
+    Ignore,
+}
+
+impl ErrorPolicy {
+    pub const NAMES: [&'static str; 3] = ["halt", "trap", "ignore"];
+
+    pub fn from_name(name: &str) -> Option<ErrorPolicy> {
+        match name.to_lowercase().as_str() {
+            "halt" => Some(ErrorPolicy::Halt),
+            "trap" => Some(ErrorPolicy::Trap),
+            "ignore" => Some(ErrorPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Traits that decouple the interpreter from whatever is driving it. The core
+// in chip8.rs only ever talks to these, so it can run under SDL, in a
+// terminal, headless in tests, or inside someone else's tool.
+use crate::chip8::NUM_KEYS;
+
+// Something that knows the state of the 16-key hex keypad. It is polled once
+// per frame; Fx0A waits by watching the keys rather than blocking in here.
+pub trait InputProvider {
+    // Refreshes `keys` with the current keypad state (index = CHIP-8 key).
+    // Returns true if the frontend wants the emulator to exit.
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool;
+}
+
+// Something that can show a finished frame
+pub trait FrameSink {
+    // `gfx` is the row-major framebuffer, one byte per pixel, non-zero = lit.
+    // XO-CHIP ROMs can use values 0-3, one bit per bitplane.
+    // `width` and `height` change when a SUPER-CHIP ROM switches resolution.
+    fn present(&mut self, gfx: &[u8], width: usize, height: usize);
+}
+
+// Something that can sound the buzzer driven by the sound timer
+pub trait AudioSink {
+    // Called once per 60 Hz tick with whether the buzzer should be on
+    fn set_beeping(&mut self, on: bool);
+}
+
+// Records the buzzer state of every 60 Hz tick instead of playing it, for
+// running without a sound card or checking a ROM's sound in tests
+#[derive(Debug, Default)]
+pub struct AudioCapture {
+    pub ticks: Vec<bool>,
+}
+
+impl AudioSink for AudioCapture {
+    fn set_beeping(&mut self, on: bool) {
+        self.ticks.push(on);
+    }
+}
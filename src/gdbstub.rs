@@ -0,0 +1,544 @@
+// GDB remote serial protocol stub, so gdb (or anything else speaking RSP)
+// can debug a ROM over TCP. Breakpoints and stepping go through the
+// Debugger, this only translates packets.
+//
+// Registers, in `g` packet order: V0-VF (8 bit), I (16 bit), PC (16 bit),
+// DT, ST and SP (8 bit; SP is the stack depth and can't be written).
+// 16 bit registers are sent big-endian, like every other CHIP-8 word.
+// The layout is also described by the target.xml served over qXfer.
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::chip8::{Chip8, NUM_VREGS};
+use crate::debugger::Debugger;
+use crate::error::Chip8Error;
+
+// Signals reported in stop replies
+pub const SIGINT: u8 = 2;
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGSEGV: u8 = 11;
+
+const REG_I: usize = NUM_VREGS;
+const REG_PC: usize = NUM_VREGS + 1;
+const REG_DT: usize = NUM_VREGS + 2;
+const REG_ST: usize = NUM_VREGS + 3;
+const REG_SP: usize = NUM_VREGS + 4;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rusty-chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// What the frontend should do once serve() returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GdbAction {
+    // Run until interrupted(), a breakpoint or a fault
+    Continue,
+    // The debugger went away, carry on without it
+    Detach,
+    // Quit the emulator
+    Kill,
+}
+
+// Stop signal for a fault
+pub fn signal_for(e: &Chip8Error) -> u8 {
+    match e {
+        Chip8Error::InvalidOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|n| u8::from_str_radix(text.get(n..n + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "addr,len" as used by m, M, Z and z
+fn parse_addr_len(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn read_register(c8: &Chip8, n: usize) -> Option<Vec<u8>> {
+    match n {
+        0..=15 => Some(vec![c8.v()[n]]),
+        REG_I => Some(c8.i().to_be_bytes().to_vec()),
+        REG_PC => Some((c8.pc() as u16).to_be_bytes().to_vec()),
+        REG_DT => Some(vec![c8.dt()]),
+        REG_ST => Some(vec![c8.st()]),
+        REG_SP => Some(vec![c8.stack().len() as u8]),
+        _ => None,
+    }
+}
+
+// Returns false for registers that don't exist or can't be written
+fn write_register(c8: &mut Chip8, n: usize, bytes: &[u8]) -> bool {
+    let word = || bytes.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    match (n, bytes.first()) {
+        (0..=15, Some(&value)) => c8.set_v(n, value),
+        (REG_I, _) => match word() {
+            Some(i) => c8.set_i(i),
+            None => return false,
+        },
+        (REG_PC, _) => match word() {
+            Some(pc) => c8.set_pc(pc as usize),
+            None => return false,
+        },
+        (REG_DT, Some(&value)) => c8.set_dt(value),
+        (REG_ST, Some(&value)) => c8.set_st(value),
+        _ => return false,
+    }
+    true
+}
+
+fn all_registers(c8: &Chip8) -> Vec<u8> {
+    (0..=REG_SP).flat_map(|n| read_register(c8, n).unwrap_or_default()).collect()
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    // Continued and not yet reported as stopped
+    running: bool,
+    // Signal of the last stop, for '?'
+    last_signal: u8,
+    // gdb asked to skip the +/- acknowledgements
+    no_ack: bool,
+}
+
+impl GdbStub {
+    // Waits for gdb to connect on localhost:port
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for gdb on 127.0.0.1:{} (target remote :{})", port, port);
+        let (stream, addr) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        println!("gdb connected from {}", addr);
+        Ok(Self { stream, running: false, last_signal: SIGTRAP, no_ack: false })
+    }
+
+    // While running, checks without blocking whether gdb sent an interrupt
+    // (Ctrl-C). A dropped connection counts too, serve() then detaches.
+    pub fn interrupted(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let mut byte = [0u8; 1];
+        let result = loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => break true,
+                Ok(_) if byte[0] == 0x03 => break true,
+                // Stray acknowledgements
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+        let _ = self.stream.set_nonblocking(false);
+        result
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes())?;
+        if !self.no_ack {
+            // Wait for the '+', a '-' asks for the packet again
+            let mut ack = [0u8; 1];
+            loop {
+                if self.stream.read(&mut ack)? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                match ack[0] {
+                    b'+' => break,
+                    b'-' => self.stream.write_all(packet.as_bytes())?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Next packet's payload, or None when gdb hung up
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0u8; 1];
+        loop {
+            // Skip to the start of a packet, ignoring acks and interrupts
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if self.no_ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            if expected == Some(actual) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn stop_reply(&mut self, signal: u8) -> io::Result<()> {
+        self.last_signal = signal;
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    // Handles packets while the machine is stopped, until gdb continues,
+    // detaches or kills it. `signal` is why it stopped.
+    pub fn serve(&mut self, c8: &mut Chip8, debugger: &mut Debugger, ipf: u32, signal: u8) -> io::Result<GdbAction> {
+        debugger.ipf = ipf;
+        if self.running {
+            self.running = false;
+            self.stop_reply(signal)?;
+        }
+        loop {
+            let packet = match self.receive()? {
+                Some(packet) => packet,
+                None => {
+                    // Hung up without removing its breakpoints
+                    debugger.breakpoints.clear();
+                    return Ok(GdbAction::Detach);
+                }
+            };
+            let (command, args) = packet.split_at(packet.len().min(1));
+            let reply = match command {
+                "?" => format!("S{:02x}", self.last_signal),
+                "g" => hex_bytes(&all_registers(c8)),
+                "G" => {
+                    let bytes = parse_hex_bytes(args).unwrap_or_default();
+                    let mut pos = 0;
+                    for n in 0..REG_SP {
+                        let width = read_register(c8, n).map_or(0, |r| r.len());
+                        if let Some(value) = bytes.get(pos..pos + width) {
+                            write_register(c8, n, value);
+                        }
+                        pos += width;
+                    }
+                    "OK".to_string()
+                }
+                "p" => match parse_hex(args).and_then(|n| read_register(c8, n)) {
+                    Some(value) => hex_bytes(&value),
+                    None => "E01".to_string(),
+                },
+                "P" => {
+                    let written = args.split_once('=').and_then(|(n, value)| {
+                        Some(write_register(c8, parse_hex(n)?, &parse_hex_bytes(value)?))
+                    });
+                    if written == Some(true) { "OK".to_string() } else { "E01".to_string() }
+                }
+                "m" => match parse_addr_len(args).and_then(|(addr, len)| c8.mem().get(addr..addr.checked_add(len)?)) {
+                    Some(bytes) => hex_bytes(bytes),
+                    None => "E01".to_string(),
+                },
+                "M" => {
+                    let write = args.split_once(':').and_then(|(range, data)| {
+                        let (addr, len) = parse_addr_len(range)?;
+                        let bytes = parse_hex_bytes(data)?;
+                        let dest = c8.mem_mut().get_mut(addr..addr.checked_add(len)?)?;
+                        if bytes.len() != len {
+                            return None;
+                        }
+                        dest.copy_from_slice(&bytes);
+                        Some(())
+                    });
+                    if write.is_some() {
+                        c8.draw_flag = true;
+                        "OK".to_string()
+                    } else {
+                        "E01".to_string()
+                    }
+                }
+                // Resuming somewhere outside memory
+                "c" | "s" if parse_hex(args).is_some_and(|addr| addr >= c8.mem().len()) => "E01".to_string(),
+                "c" => {
+                    if let Some(addr) = parse_hex(args) {
+                        c8.set_pc(addr);
+                    }
+                    debugger.resume();
+                    self.running = true;
+                    return Ok(GdbAction::Continue);
+                }
+                "s" => {
+                    if let Some(addr) = parse_hex(args) {
+                        c8.set_pc(addr);
+                    }
+                    let signal = match debugger.step_in_frame(c8) {
+                        Ok(_) => SIGTRAP,
+                        Err(e) => signal_for(&e),
+                    };
+                    self.stop_reply(signal)?;
+                    continue;
+                }
+                // Software and hardware breakpoints are the same thing here
+                "Z" | "z" => match args.split_once(',').map(|(kind, rest)| (kind, parse_addr_len(rest))) {
+                    Some(("0" | "1", Some((addr, _)))) => {
+                        if command == "Z" {
+                            debugger.breakpoints.insert(addr);
+                        } else {
+                            debugger.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new(),
+                },
+                "k" => return Ok(GdbAction::Kill),
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(GdbAction::Detach);
+                }
+                // Only one thread
+                "H" | "T" => "OK".to_string(),
+                "Q" if packet == "QStartNoAckMode" => {
+                    // This reply still gets acknowledged, nothing after it
+                    self.send("OK")?;
+                    self.no_ack = true;
+                    continue;
+                }
+                "q" => self.query(&packet),
+                _ => String::new(),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = parse_addr_len(range).unwrap_or((0, 0));
+            let xml = TARGET_XML.as_bytes();
+            let end = offset.saturating_add(len);
+            let chunk = &xml[offset.min(xml.len())..end.min(xml.len())];
+            let more = end < xml.len();
+            return format!("{}{}", if more { "m" } else { "l" }, String::from_utf8_lossy(chunk));
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    // gdb's end of the connection
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn byte(&mut self) -> u8 {
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        // Sends a packet and returns the stub's reply, acknowledging it
+        fn packet(&mut self, data: &str) -> String {
+            let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+            assert_eq!(self.byte(), b'+');
+            self.reply()
+        }
+
+        // Sends a packet the stub doesn't answer, like k or c
+        fn send(&mut self, data: &str) {
+            let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+            assert_eq!(self.byte(), b'+');
+        }
+
+        fn reply(&mut self) -> String {
+            assert_eq!(self.byte(), b'$');
+            let mut data = Vec::new();
+            loop {
+                match self.byte() {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+            let checksum = [self.byte(), self.byte()];
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+            assert_eq!(data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), expected);
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+    }
+
+    fn machine(program: &[u16]) -> Chip8 {
+        let mut c8 = Chip8::new();
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        c8.load_rom_bytes(&rom).unwrap();
+        c8
+    }
+
+    // Runs serve() on c8 while `gdb` talks to it from another thread
+    fn session(c8: &mut Chip8, debugger: &mut Debugger, gdb: impl FnOnce(&mut Client) + Send) -> GdbAction {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::scope(|s| {
+            s.spawn(move || {
+                let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                gdb(&mut Client { stream });
+            });
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut stub = GdbStub { stream, running: false, last_signal: SIGTRAP, no_ack: false };
+            stub.serve(c8, debugger, 1, SIGTRAP).unwrap()
+        })
+    }
+
+    #[test]
+    fn registers() {
+        let mut c8 = machine(&[0x6012]);
+        c8.set_v(0xF, 0x34);
+        c8.set_i(0x0ABC);
+        c8.set_dt(7);
+        let action = session(&mut c8, &mut Debugger::new(), |gdb| {
+            let regs = gdb.packet("g");
+            assert_eq!(regs, format!("{}34{}{}{}", "00".repeat(15), "0abc", "0200", "070000"));
+            // All of them back with V0 = 0x55, I = 0x0123, PC = 0x0300, ST = 9
+            let new = format!("55{}{}{}{}", "00".repeat(15), "0123", "0300", "000900");
+            assert_eq!(gdb.packet(&format!("G{}", new)), "OK");
+            assert_eq!(gdb.packet("g"), new);
+            assert_eq!(gdb.packet("p11"), "0300");
+            assert_eq!(gdb.packet("P3=ab"), "OK");
+            // SP can't be written, and there's no register 0x15
+            assert_eq!(gdb.packet("P14=01"), "E01");
+            assert_eq!(gdb.packet("p15"), "E01");
+            gdb.send("k");
+        });
+        assert_eq!(action, GdbAction::Kill);
+        assert_eq!((c8.v()[0], c8.v()[3], c8.i(), c8.pc(), c8.st()), (0x55, 0xAB, 0x0123, 0x300, 9));
+    }
+
+    #[test]
+    fn memory_and_out_of_range_addresses() {
+        let mut c8 = machine(&[0x6012, 0x1202]);
+        let end = c8.mem().len();
+        session(&mut c8, &mut Debugger::new(), move |gdb| {
+            assert_eq!(gdb.packet("m200,4"), "60121202");
+            assert_eq!(gdb.packet("M300,3:a1b2c3"), "OK");
+            assert_eq!(gdb.packet("m300,3"), "a1b2c3");
+            // Ending exactly at the end of memory is fine, one byte past isn't
+            assert_eq!(gdb.packet(&format!("m{:x},1", end - 1)), "00");
+            assert_eq!(gdb.packet(&format!("m{:x},2", end - 1)), "E01");
+            assert_eq!(gdb.packet(&format!("m{:x},1", end)), "E01");
+            assert_eq!(gdb.packet(&format!("M{:x},2:0102", end - 1)), "E01");
+            // addr + len overflowing usize
+            assert_eq!(gdb.packet("m1,ffffffffffffffff"), "E01");
+            assert_eq!(gdb.packet("Mffffffffffffffff,1:00"), "E01");
+            // Length and data disagree, or bad hex
+            assert_eq!(gdb.packet("M300,2:01"), "E01");
+            assert_eq!(gdb.packet("M300,1:zz"), "E01");
+            gdb.send("k");
+        });
+        assert_eq!(&c8.mem()[0x300..0x303], &[0xA1, 0xB2, 0xC3]);
+        assert!(c8.draw_flag);
+    }
+
+    #[test]
+    fn breakpoints_step_and_continue() {
+        let mut c8 = machine(&[0x6012, 0x7001, 0x1202]);
+        let mut debugger = Debugger::new();
+        let end = c8.mem().len();
+        let action = session(&mut c8, &mut debugger, move |gdb| {
+            assert_eq!(gdb.packet("Z0,204,2"), "OK");
+            assert_eq!(gdb.packet("Z1,206,2"), "OK");
+            assert_eq!(gdb.packet("z1,206,2"), "OK");
+            // Watchpoints aren't supported
+            assert_eq!(gdb.packet("Z2,300,1"), "");
+            assert_eq!(gdb.packet("s"), "S05");
+            assert_eq!(gdb.packet("g")[32 + 4..32 + 8], *"0202");
+            // Stepping or continuing from outside memory
+            assert_eq!(gdb.packet(&format!("s{:x}", end)), "E01");
+            assert_eq!(gdb.packet(&format!("c{:x}", end)), "E01");
+            assert_eq!(gdb.packet("s200"), "S05");
+            gdb.send("c");
+        });
+        assert_eq!(action, GdbAction::Continue);
+        assert_eq!(debugger.breakpoints.iter().copied().collect::<Vec<_>>(), [0x204]);
+        assert_eq!(c8.pc(), 0x202);
+        assert_eq!(c8.v()[0], 0x12);
+    }
+
+    #[test]
+    fn bad_checksums_are_nacked() {
+        let mut c8 = machine(&[0x6012]);
+        session(&mut c8, &mut Debugger::new(), |gdb| {
+            gdb.stream.write_all(b"$g#00").unwrap();
+            assert_eq!(gdb.byte(), b'-');
+            // The retransmission goes through, and a '-' gets the reply again
+            gdb.stream.write_all(b"$?#3f").unwrap();
+            assert_eq!(gdb.byte(), b'+');
+            let mut reply = [0u8; 7];
+            gdb.stream.read_exact(&mut reply).unwrap();
+            assert_eq!(&reply, b"$S05#b8");
+            gdb.stream.write_all(b"-").unwrap();
+            assert_eq!(gdb.reply(), "S05");
+            gdb.send("k");
+        });
+    }
+}
@@ -1,51 +1,180 @@
-use sdl2::render::WindowCanvas;
-use sdl2::pixels;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::pixels::{self, PixelFormatEnum};
 use sdl2::rect::Rect;
-use std::vec::Vec;
-use crate::chip8::Chip8;
-use crate::chip8::PIXEL_SIZE;
-use crate::chip8::DISP_X;
-use crate::chip8::DISP_Y;
-pub struct Display;
+use sdl2::video::{FullscreenType, WindowContext};
+use std::fmt;
+use crate::crt::Crt;
+use crate::frontend::FrameSink;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+
+// How the image is scaled up to fill the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    // Largest whole number of window pixels per CHIP-8 pixel, so every
+    // pixel is the same size
+    #[default]
+    Integer,
+    // As large as fits, pixels may differ in size by one
+    Smooth,
+}
+
+impl ScaleMode {
+    pub const NAMES: [&'static str; 2] = ["integer", "smooth"];
+
+    pub fn from_name(name: &str) -> Option<ScaleMode> {
+        match name.to_lowercase().as_str() {
+            "integer" => Some(ScaleMode::Integer),
+            "smooth" => Some(ScaleMode::Smooth),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ScaleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Smooth => "smooth",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Where a width x height image goes in a window of win_w x win_h pixels:
+// centered and as large as fits, leaving black bars on the other sides
+pub fn viewport(win_w: u32, win_h: u32, width: usize, height: usize, mode: ScaleMode) -> Rect {
+    let (width, height) = (width as u32, height as u32);
+    let (w, h) = match mode {
+        ScaleMode::Integer => {
+            let scale = (win_w / width).min(win_h / height).max(1);
+            (width * scale, height * scale)
+        }
+        // Whichever side is the tighter fit decides
+        ScaleMode::Smooth if win_w * height <= win_h * width => (win_w, win_w * height / width),
+        ScaleMode::Smooth => (win_h * width / height, win_h),
+    };
+    let x = (win_w as i32 - w as i32) / 2;
+    let y = (win_h as i32 - h as i32) / 2;
+    Rect::new(x, y, w.max(1), h.max(1))
+}
+
+// Draws frames by uploading them into a streaming texture the size of the
+// CHIP-8 screen and letting the GPU scale it to the window. With CRT effects
+// on, the picture is scaled on the CPU instead and the texture is the size
+// of the image in the window.
+pub struct Display {
+    canvas: WindowCanvas,
+    // Leaked so the texture can borrow it for 'static; there is only ever
+    // one and it lives as long as the program anyway
+    texture_creator: &'static TextureCreator<WindowContext>,
+    // Recreated when the size it needs changes, along with its size
+    texture: Option<(Texture<'static>, usize, usize)>,
+    pub palette: Palette,
+    pub scale_mode: ScaleMode,
+    pub phosphor: Phosphor,
+    pub crt: Crt,
+}
 
 impl Display {
-    pub fn clear(canvas: &mut WindowCanvas){
-        canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-    }
-    pub fn render_gfx(chip8: &mut Chip8, canvas: &mut WindowCanvas){
-        let mut set_vec: Vec<Rect> = Vec::new();
-        let mut unset_vec: Vec<Rect> = Vec::new();
-        {
-            let mut x_pos = 1;
-            let mut y_pos = 1;
-            if chip8.draw_flag {
-                chip8.draw_flag = false;
-                for (i, _px) in chip8.gfx.iter().enumerate() {
-                    if chip8.gfx[i] != 0 {
-                        set_vec.push(Rect::new(x_pos * PIXEL_SIZE as i32, 
-                                               y_pos * PIXEL_SIZE as i32, 
-                                               PIXEL_SIZE as u32, 
-                                               PIXEL_SIZE as u32));
-                    } else {
-                        unset_vec.push(Rect::new(x_pos * PIXEL_SIZE as i32, 
-                                                 y_pos * PIXEL_SIZE as i32, 
-                                                 PIXEL_SIZE as u32, 
-                                                 PIXEL_SIZE as u32));
-                    }
-                    if ((i+1) % DISP_X) == 0 {
-                        y_pos += 1;
-                        x_pos = 0;
-                    }
-                    x_pos += 1;
+    pub fn new(sdl_context: &sdl2::Sdl, title: &str, width: u32, height: u32) -> Self {
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let window = video_subsystem.window(title, width, height)
+            .position_centered()
+            .resizable()
+            .build()
+            .unwrap();
+
+        // Scale the texture up with sharp pixels
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let canvas = window.into_canvas()
+            .accelerated()
+            // .present_vsync()
+            .build()
+            .unwrap();
+
+        let texture_creator = Box::leak(Box::new(canvas.texture_creator()));
+        let mut display = Self {
+            canvas,
+            texture_creator,
+            texture: None,
+            palette: Palette::default(),
+            scale_mode: ScaleMode::default(),
+            phosphor: Phosphor::default(),
+            crt: Crt::default(),
+        };
+        display.clear();
+        display
+    }
+    pub fn clear(&mut self){
+        self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.present();
+    }
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+    // Switches between the window and the whole screen at the desktop's
+    // resolution
+    pub fn set_fullscreen(&mut self, on: bool) {
+        let mode = if on { FullscreenType::Desktop } else { FullscreenType::Off };
+        if let Err(e) = self.canvas.window_mut().set_fullscreen(mode) {
+            eprintln!("Could not change fullscreen mode: {}", e);
+        }
+    }
+    // Fills the texture with gfx in the palette's colours, after phosphor
+    // persistence and CRT effects, creating it first if there is none of the
+    // right size yet
+    fn upload(&mut self, gfx: &[u8], width: usize, height: usize, view: Rect) -> Result<(), String> {
+        let (tex_w, tex_h) = if self.crt.enabled() {
+            (view.width() as usize, view.height() as usize)
+        } else {
+            (width, height)
+        };
+        if !matches!(self.texture, Some((_, w, h)) if w == tex_w && h == tex_h) {
+            let texture = self.texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, tex_w as u32, tex_h as u32)
+                .map_err(|e| e.to_string())?;
+            self.texture = Some((texture, tex_w, tex_h));
+        }
+        let colors = self.phosphor.apply(gfx, width, height, &self.palette.colors);
+        let (texture, _, _) = self.texture.as_mut().unwrap();
+        if self.crt.enabled() {
+            let image = self.crt.render(colors, width, height, tex_w, tex_h);
+            return texture.update(None, image, tex_w * 3).map_err(|e| e.to_string());
+        }
+        texture.with_lock(None, |pixels, pitch| {
+            for (row, line) in colors.chunks(width).take(height).enumerate() {
+                let out = &mut pixels[row * pitch..row * pitch + width * 3];
+                for (&(r, g, b), rgb) in line.iter().zip(out.chunks_mut(3)) {
+                    rgb.copy_from_slice(&[r, g, b]);
                 }
             }
+        })
+    }
+    // Draws a width x height framebuffer scaled to fit the window and
+    // presents it. The size is looked up every time, so resizing the window,
+    // going fullscreen or changing the display's resolution are all picked up.
+    pub fn render_gfx(&mut self, gfx: &[u8], width: usize, height: usize){
+        let (win_w, win_h) = self.canvas.output_size().unwrap_or((width as u32, height as u32));
+        let view = viewport(win_w, win_h, width, height, self.scale_mode);
+        if let Err(e) = self.upload(gfx, width, height, view) {
+            eprintln!("Could not update the screen texture: {}", e);
+            return;
+        }
+        // Letterbox bars
+        self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        if let Some((texture, _, _)) = &self.texture {
+            self.canvas.copy(texture, None, view).ok();
         }
-        canvas.set_draw_color(pixels::Color::RGB(255, 255, 255));
-        canvas.fill_rects(&set_vec).ok();
-        canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
-        canvas.fill_rects(&unset_vec).ok();
-        canvas.present();
+        self.canvas.present();
+    }
+}
+
+impl FrameSink for Display {
+    fn present(&mut self, gfx: &[u8], width: usize, height: usize) {
+        self.render_gfx(gfx, width, height);
     }
 }
@@ -0,0 +1,421 @@
+// Writes framebuffers out as image files, for screenshots, GIF recordings
+// and checking what a ROM drew without a window. Hand rolled so the core
+// still only depends on rand: PNGs are palette images stored without
+// compression, which is tiny at CHIP-8 resolutions anyway.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::palette::Rgb;
+use crate::scheduler::FRAME_RATE;
+
+// Plain (text) PBM, lit pixels are 1. Only tells lit from unlit, so the
+// XO-CHIP bitplanes are merged.
+pub fn write_pbm(out: &mut dyn Write, gfx: &[u8], width: usize, height: usize) -> io::Result<()> {
+    writeln!(out, "P1")?;
+    writeln!(out, "{} {}", width, height)?;
+    for row in gfx.chunks(width).take(height) {
+        let bits: Vec<&str> = row.iter().map(|&px| if px != 0 { "1" } else { "0" }).collect();
+        writeln!(out, "{}", bits.join(" "))?;
+    }
+    Ok(())
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    out.write_all(&body)?;
+    out.write_all(&crc32(&body).to_be_bytes())
+}
+
+// zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// PNG with every CHIP-8 pixel blown up to scale x scale
+pub fn write_png(out: &mut dyn Write, gfx: &[u8], width: usize, height: usize,
+                 colors: &[Rgb; 4], scale: usize) -> io::Result<()> {
+    let scale = scale.max(1);
+    let (out_w, out_h) = (width * scale, height * scale);
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(out_w as u32).to_be_bytes());
+    header.extend_from_slice(&(out_h as u32).to_be_bytes());
+    // 8 bits per pixel, palette colour, default compression/filter/interlace
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    let palette: Vec<u8> = colors.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+    write_chunk(out, b"PLTE", &palette)?;
+
+    // Each scanline starts with its filter type, 0 = none
+    let mut pixels = Vec::with_capacity((out_w + 1) * out_h);
+    for y in 0..out_h {
+        pixels.push(0);
+        let row = &gfx[(y / scale) * width..(y / scale + 1) * width];
+        for x in 0..out_w {
+            pixels.push(row[x / scale] & 0x3);
+        }
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&pixels))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+// Default pixels per CHIP-8 pixel for screenshots and GIFs
+pub const DEFAULT_CAPTURE_SCALE: usize = 8;
+// Where the SDL frontend puts them
+pub const CAPTURE_DIR: &str = "./captures";
+
+// Nearest neighbour resize of a framebuffer to out_w x out_h
+pub fn resize(gfx: &[u8], width: usize, height: usize, out_w: usize, out_h: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(out_w * out_h);
+    for y in 0..out_h {
+        let row = &gfx[(y * height / out_h) * width..];
+        out.extend((0..out_w).map(|x| row[x * width / out_w] & 0x3));
+    }
+    out
+}
+
+// Free path in CAPTURE_DIR for a capture of the ROM at rom_path, like
+// captures/PONG-3.png
+pub fn capture_path(rom_path: &str, extension: &str) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(CAPTURE_DIR)?;
+    let name = Path::new(rom_path).file_name().map_or("rom".into(), |n| n.to_string_lossy());
+    let mut n = 1;
+    loop {
+        let path = Path::new(CAPTURE_DIR).join(format!("{}-{}.{}", name, n, extension));
+        if !path.exists() {
+            return Ok(path);
+        }
+        n += 1;
+    }
+}
+
+// GIF LZW, packed least significant bit first
+fn lzw(min_code_size: u8, data: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let mut code_size = min_code_size as u32 + 1;
+    let mut emit = |code: u16, code_size: u32| {
+        acc |= (code as u32) << bits;
+        bits += code_size;
+        while bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    emit(clear, code_size);
+    let mut pixels = data.iter();
+    if let Some(&first) = pixels.next() {
+        let mut prefix = first as u16;
+        for &px in pixels {
+            if let Some(&code) = table.get(&(prefix, px)) {
+                prefix = code;
+                continue;
+            }
+            emit(prefix, code_size);
+            if next < 4096 {
+                table.insert((prefix, px), next);
+                next += 1;
+                // The decoder adds its entries one code later
+                if next > 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            } else {
+                emit(clear, code_size);
+                table.clear();
+                next = end + 1;
+                code_size = min_code_size as u32 + 1;
+            }
+            prefix = px as u16;
+        }
+        emit(prefix, code_size);
+    }
+    emit(end, code_size);
+    if bits > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
+
+// Streams an animated GIF out a frame at a time. Every frame is the full
+// canvas, pixels are gfx values (0-3) looked up in the palette.
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(mut out: W, width: usize, height: usize, colors: &[Rgb; 4]) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        // Global colour table of 4 entries, background 0, square pixels
+        out.write_all(&[0x81, 0, 0])?;
+        for &(r, g, b) in colors.iter() {
+            out.write_all(&[r, g, b])?;
+        }
+        // Loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(Self { out, width, height })
+    }
+
+    // Adds a frame shown for delay hundredths of a second
+    pub fn frame(&mut self, pixels: &[u8], delay: u16) -> io::Result<()> {
+        let delay = delay.to_le_bytes();
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00, delay[0], delay[1], 0x00, 0x00])?;
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, 2])?;
+        for block in lzw(2, &pixels[..self.width * self.height]).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Records what was on screen at the end of every 60 Hz frame into a GIF.
+// GIF delays are in hundredths of a second and most viewers slow down
+// anything shorter than 2, so frames are timed from the 60 Hz clock and a
+// picture that would be shown for less than that is replaced by the next.
+pub struct GifRecorder {
+    writer: GifWriter<BufWriter<File>>,
+    width: usize,
+    height: usize,
+    // Picture waiting to be written and the frame it appeared on
+    pending: Option<(Vec<u8>, u64)>,
+    frame: u64,
+}
+
+impl GifRecorder {
+    // The canvas is the current screen scaled up; if the ROM changes
+    // resolution later its frames are resized to fit
+    pub fn create(path: &Path, width: usize, height: usize, colors: &[Rgb; 4],
+                  scale: usize) -> io::Result<Self> {
+        let (width, height) = (width * scale.max(1), height * scale.max(1));
+        let writer = GifWriter::new(BufWriter::new(File::create(path)?), width, height, colors)?;
+        Ok(Self { writer, width, height, pending: None, frame: 0 })
+    }
+
+    // Hundredths of a second from frame a to frame b
+    fn delay(a: u64, b: u64) -> u64 {
+        let centis = |frame: u64| (frame * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+        centis(b) - centis(a)
+    }
+
+    pub fn capture(&mut self, gfx: &[u8], width: usize, height: usize) -> io::Result<()> {
+        let pixels = resize(gfx, width, height, self.width, self.height);
+        let frame = self.frame;
+        self.frame += 1;
+        match self.pending.take() {
+            // Nothing changed, the pending picture just stays up longer
+            Some((pending, start)) if pending == pixels => self.pending = Some((pending, start)),
+            Some((pending, start)) if Self::delay(start, frame) >= 2 => {
+                self.writer.frame(&pending, Self::delay(start, frame) as u16)?;
+                self.pending = Some((pixels, frame));
+            }
+            Some((_, start)) => self.pending = Some((pixels, start)),
+            None => self.pending = Some((pixels, frame)),
+        }
+        Ok(())
+    }
+
+    // Writes the last picture and closes the file. Returns the length in frames.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if let Some((pending, start)) = self.pending.take() {
+            self.writer.frame(&pending, Self::delay(start, self.frame).max(2) as u16)?;
+        }
+        self.writer.finish()?;
+        Ok(self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [Rgb; 4] = [(255, 255, 255), (255, 0, 0), (0, 0, 255), (0, 0, 0)];
+
+    // Reads LZW codes back into pixels, the way a GIF viewer does
+    fn unlzw(min_code_size: u8, data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let fresh = || (0..clear).map(|n| vec![n as u8]).chain([vec![], vec![]]).collect::<Vec<_>>();
+        let mut table = fresh();
+        let mut code_size = min_code_size as usize + 1;
+        let (mut pos, mut out, mut prev) = (0, Vec::new(), None::<usize>);
+        loop {
+            let code = (0..code_size).fold(0, |code, n| {
+                code | ((data[(pos + n) / 8] as usize >> ((pos + n) % 8)) & 1) << n
+            });
+            pos += code_size;
+            if code == clear {
+                (table, code_size, prev) = (fresh(), min_code_size as usize + 1, None);
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match prev {
+                Some(prev) => {
+                    let mut entry = table[prev].clone();
+                    entry.push(table.get(code).unwrap_or(&table[prev])[0]);
+                    if table.len() < 4096 {
+                        table.push(entry.clone());
+                    }
+                    if table.len() == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                    if code < table.len() - 1 { table[code].clone() } else { entry }
+                }
+                None => table[code].clone(),
+            };
+            out.extend_from_slice(&entry);
+            prev = Some(code);
+        }
+    }
+
+    #[test]
+    fn checksums_match_the_standard_ones() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn zlib_blocks_are_stored() {
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]);
+        assert_eq!(zlib_stored(b"abc"), [0x78, 0x01, 1, 3, 0, 0xFC, 0xFF, b'a', b'b', b'c', 0x02, 0x4D, 0x01, 0x27]);
+
+        // Too much for one block: a full one, then the final one
+        let data: Vec<u8> = (0..70_000).map(|n| n as u8).collect();
+        let out = zlib_stored(&data);
+        assert_eq!(out.len(), 2 + 5 + 0xFFFF + 5 + (70_000 - 0xFFFF) + 4);
+        assert_eq!(out[2..7], [0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(out[7 + 0xFFFF..12 + 0xFFFF], [1, 0x71, 0x11, 0x8E, 0xEE]);
+        assert_eq!(out[out.len() - 4..], adler32(&data).to_be_bytes());
+    }
+
+    #[test]
+    fn png_chunks_check_out() {
+        let gfx = [0, 1, 2, 3, 3, 2];
+        let mut png = Vec::new();
+        write_png(&mut png, &gfx, 3, 2, &COLORS, 2).unwrap();
+        assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let body = &rest[4..8 + len];
+            assert_eq!(rest[8 + len..12 + len], crc32(body).to_be_bytes());
+            chunks.push((&body[..4], &body[4..]));
+            rest = &rest[12 + len..];
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|&(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"PLTE", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 6, 0, 0, 0, 4, 8, 3, 0, 0, 0]);
+        assert_eq!(chunks[1].1, [255, 255, 255, 255, 0, 0, 0, 0, 255, 0, 0, 0]);
+        // Each pixel doubled both ways, every row behind a filter byte
+        let rows = [0, 0, 0, 1, 1, 2, 2, 0, 0, 0, 1, 1, 2, 2, 0, 3, 3, 3, 3, 2, 2, 0, 3, 3, 3, 3, 2, 2];
+        assert_eq!(chunks[2].1, zlib_stored(&rows));
+    }
+
+    #[test]
+    fn lzw_matches_reference_bytes() {
+        // The 10x10 sample image from "What's in a GIF", which spells out
+        // every code its encoder emits
+        let rows = ["1111122222", "1111122222", "1111122222", "1110000222", "1110000222",
+                    "2220000111", "2220000111", "2222211111", "2222211111", "2222211111"];
+        let pixels: Vec<u8> = rows.iter().flat_map(|row| row.bytes().map(|b| b - b'0')).collect();
+        assert_eq!(lzw(2, &pixels), [
+            0x8C, 0x2D, 0x99, 0x87, 0x2A, 0x1C, 0xDC, 0x33, 0xA0, 0x02, 0x75,
+            0xEC, 0x95, 0xFA, 0xA8, 0xDE, 0x60, 0x8C, 0x04, 0x91, 0x4C, 0x01,
+        ]);
+        // Nothing at all is a clear and an end code
+        assert_eq!(lzw(2, &[]), [0x2C]);
+        assert_eq!(unlzw(2, &lzw(2, &pixels)), pixels);
+    }
+
+    #[test]
+    fn lzw_survives_a_full_table() {
+        // Noisy enough to fill all 4096 codes a few times over
+        let mut seed: u32 = 0x2545F491;
+        let pixels: Vec<u8> = (0..64 * 32 * 64).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed >> 7) as u8 & 0x3
+        }).collect();
+        assert_eq!(unlzw(2, &lzw(2, &pixels)), pixels);
+        let flat = vec![1; 128 * 64 * 8];
+        assert_eq!(unlzw(2, &lzw(2, &flat)), flat);
+    }
+
+    #[test]
+    fn gif_frames_are_laid_out() {
+        let mut gif = GifWriter::new(Vec::new(), 2, 2, &COLORS).unwrap();
+        gif.frame(&[0, 1, 2, 3], 5).unwrap();
+        let gif = gif.finish().unwrap();
+        assert_eq!(gif[..13], *b"GIF89a\x02\x00\x02\x00\x81\x00\x00");
+        let frame = 13 + 12 + 19;
+        assert_eq!(gif[13..25], [255, 255, 255, 255, 0, 0, 0, 0, 255, 0, 0, 0]);
+        assert_eq!(gif[frame..frame + 8], [0x21, 0xF9, 0x04, 0x00, 5, 0, 0, 0]);
+        assert_eq!(gif[frame + 8..frame + 19], [0x2C, 0, 0, 0, 0, 2, 0, 2, 0, 0x00, 2]);
+        let data = lzw(2, &[0, 1, 2, 3]);
+        assert_eq!(gif[frame + 19] as usize, data.len());
+        assert_eq!(gif[frame + 20..frame + 20 + data.len()], data);
+        assert_eq!(gif[frame + 20 + data.len()..], [0, 0x3B]);
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
-use sdl2::keyboard::Keycode;
+use sdl2::EventPump;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 
-use crate::chip8::Chip8;
+use crate::chip8::NUM_KEYS;
+use crate::frontend::InputProvider;
 // Provides a clean way of constructing a hashmap via a macro
 // https://stackoverflow.com/questions/28392008/is-there-a-more-concise-or-declarative-way-to-initialize-a-hashmap
 macro_rules! hashmap {
@@ -11,13 +14,51 @@ macro_rules! hashmap {
             map
     }}
 }
-// All keyboard input logic is handled in here
+// Emulator controls that are not part of the CHIP-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    ToggleMute,
+    SpeedUp,
+    SpeedDown,
+    Pause,
+    CycleTrace,
+    // Break into the debugger
+    Debug,
+    // Ctrl+0-9
+    SaveState(u8),
+    // Shift+0-9
+    LoadState(u8),
+    // F12 saves the screen scaled up, Shift+F12 at its native size
+    Screenshot { native: bool },
+    // Start or stop recording a GIF
+    ToggleGif,
+    CyclePalette,
+    // F11 or Alt+Enter
+    ToggleFullscreen,
+}
+
+// Save state slot for the number keys
+fn slot(key: Keycode) -> Option<u8> {
+    let slots = [Keycode::Num0, Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+                 Keycode::Num5, Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9];
+    slots.iter().position(|&k| k == key).map(|n| n as u8)
+}
+
+// All SDL keyboard input logic is handled in here
 pub struct Handler {
-    pub key_map: HashMap<Keycode, i32>,
+    pub key_map: HashMap<Keycode, u8>,
+    pub hotkey_map: HashMap<Keycode, Hotkey>,
+    // Hotkeys pressed since the last take_hotkeys()
+    hotkeys: Vec<Hotkey>,
+    // Backspace is held down
+    pub rewinding: bool,
+    // The window changed size or was uncovered and needs drawing again
+    pub redraw: bool,
+    event_pump: EventPump,
 }
 
 impl Handler {
-    pub fn new() -> Self{
+    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
         // using hashmap macro
         // <K = Keycode, V = CHIP-8 key index>
         let key_map = hashmap![
@@ -26,146 +67,41 @@ impl Handler {
             Keycode::A => 0x7, Keycode::S => 0x8, Keycode::D => 0x9, Keycode::F => 0xE,
             Keycode::Z => 0xA, Keycode::X => 0x0, Keycode::C => 0xB, Keycode::V => 0xF
         ];
-        Self { key_map }
-    } 
-    // TODO: This shit is horrible, find a better way to write this.
-    // Returns true if the chip8 should exit
-    pub fn set_chip8_keys(self: &Self, chip8: &mut Chip8, sdl_context: &sdl2::Sdl) -> bool {
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        // I want to do something like this, but how???
-        // for (key, val) in &self.key_map {
-        //     let key_idx = *val as usize;
-        //     // Check if key is Event::KeyDown
-        //     // Set chip8.keys[val] to true
-        //     // Check if key is Event::KeyUp
-        //     // Set chip8.keys[val] to false
-        // }
-        for event in event_pump.poll_iter() {
-            use sdl2::event::Event;
-            match event {
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => 
-                    return true,
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => 
-                    chip8.keys[self.key_map[&Keycode::Num1] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num2] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num3] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num4] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Q] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::W), .. } =>
-                    chip8.keys[self.key_map[&Keycode::W] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::E), .. } =>
-                    chip8.keys[self.key_map[&Keycode::E] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::R), .. } =>
-                    chip8.keys[self.key_map[&Keycode::R] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::A), .. } =>
-                    chip8.keys[self.key_map[&Keycode::A] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::S), .. } =>
-                    chip8.keys[self.key_map[&Keycode::S] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::D), .. } =>
-                    chip8.keys[self.key_map[&Keycode::D] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::F), .. } =>
-                    chip8.keys[self.key_map[&Keycode::F] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Z] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::X), .. } =>
-                    chip8.keys[self.key_map[&Keycode::X] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::C), .. } =>
-                    chip8.keys[self.key_map[&Keycode::C] as usize] = true,
-                Event::KeyDown { keycode: Some(Keycode::V), .. } =>
-                    chip8.keys[self.key_map[&Keycode::V] as usize] = true,
-
-                Event::KeyUp { keycode: Some(Keycode::Num1), .. } => 
-                    chip8.keys[self.key_map[&Keycode::Num1] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::Num2), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num2] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::Num3), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num3] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::Num4), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Num4] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::Q), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Q] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::W), .. } =>
-                    chip8.keys[self.key_map[&Keycode::W] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::E), .. } =>
-                    chip8.keys[self.key_map[&Keycode::E] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::R), .. } =>
-                    chip8.keys[self.key_map[&Keycode::R] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::A), .. } =>
-                    chip8.keys[self.key_map[&Keycode::A] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::S), .. } =>
-                    chip8.keys[self.key_map[&Keycode::S] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::D), .. } =>
-                    chip8.keys[self.key_map[&Keycode::D] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::F), .. } =>
-                    chip8.keys[self.key_map[&Keycode::F] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::Z), .. } =>
-                    chip8.keys[self.key_map[&Keycode::Z] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::X), .. } =>
-                    chip8.keys[self.key_map[&Keycode::X] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::C), .. } =>
-                    chip8.keys[self.key_map[&Keycode::C] as usize] = false,
-                Event::KeyUp { keycode: Some(Keycode::V), .. } =>
-                    chip8.keys[self.key_map[&Keycode::V] as usize] = false,
-                
-                _ => {}
-            }
-        }
-        return false;
+        let hotkey_map = hashmap![
+            Keycode::M => Hotkey::ToggleMute,
+            Keycode::Equals => Hotkey::SpeedUp,
+            Keycode::Minus => Hotkey::SpeedDown,
+            Keycode::P => Hotkey::Pause,
+            Keycode::T => Hotkey::CycleTrace,
+            Keycode::B => Hotkey::Debug,
+            Keycode::F12 => Hotkey::Screenshot { native: false },
+            Keycode::F9 => Hotkey::ToggleGif,
+            Keycode::F2 => Hotkey::CyclePalette,
+            Keycode::F11 => Hotkey::ToggleFullscreen
+        ];
+        let event_pump = sdl_context.event_pump().unwrap();
+        Self { key_map, hotkey_map, hotkeys: Vec::new(), rewinding: false, redraw: false, event_pump }
     }
-    // Stops thread and does not continue until a valid key is pressed. 
-    // Returns the index of that key.
-    pub fn wait_for_key(self: &Self, sdl_context: &sdl2::Sdl) -> u8 {
-        use sdl2::event::Event;
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        loop {
-            let event = event_pump.wait_event();
-
-            // TODO: This is stupid and there should be a better way to do this
-            match event {
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return 0x69,
-
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => return 0x1,
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => return 0x2,
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => return 0x3,
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => return 0xC,
 
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => return 0x4,
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => return 0x5,
-                Event::KeyDown { keycode: Some(Keycode::E), .. } => return 0x6,
-                Event::KeyDown { keycode: Some(Keycode::R), .. } => return 0xD,
-
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => return 0x7,
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => return 0x8,
-                Event::KeyDown { keycode: Some(Keycode::D), .. } => return 0x9,
-                Event::KeyDown { keycode: Some(Keycode::F), .. } => return 0xE,
-
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } => return 0xA,
-                Event::KeyDown { keycode: Some(Keycode::X), .. } => return 0x0,
-                Event::KeyDown { keycode: Some(Keycode::C), .. } => return 0xB,
-                Event::KeyDown { keycode: Some(Keycode::V), .. } => return 0xF,
-                _ => {} 
-            }
-        }
+    // Returns the hotkeys pressed since the last call, oldest first
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
     }
-    pub fn print_chip8_keys(self: &Self, chip8: &Chip8){
-        let keys = chip8.keys; 
+
+    pub fn print_chip8_keys(&self, keys: &[bool; NUM_KEYS]){
         // 1 2 3 C
-        for i in 0x1..0x3+1 {
-            print!("{} ", keys[i] as u8);
+        for key in &keys[0x1..0x3+1] {
+            print!("{} ", *key as u8);
         }
         println!("{}", keys[0xC] as u8);
         // 4 5 6 D
-        for i in 0x4..0x6+1 {
-            print!("{} ", keys[i] as u8);
+        for key in &keys[0x4..0x6+1] {
+            print!("{} ", *key as u8);
         }
         println!("{}", keys[0xD] as u8);
         // 7 8 9 E
-        for i in 0x7..0x9+1 {
-            print!("{} ", keys[i] as u8);
+        for key in &keys[0x7..0x9+1] {
+            print!("{} ", *key as u8);
         }
         println!("{}", keys[0xE] as u8);
         // A 0 B F
@@ -174,6 +110,54 @@ impl Handler {
         print!("{} ", keys[0xB] as u8);
         println!("{} ", keys[0xF] as u8);
     }
-
 }
 
+impl InputProvider for Handler {
+    // Returns true if the chip8 should exit
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>
+                    return true,
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } =>
+                    self.rewinding = true,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } =>
+                    self.rewinding = false,
+                Event::Window { win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed, .. } |
+                Event::Display { .. } =>
+                    self.redraw = true,
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+                    self.hotkeys.push(Hotkey::ToggleFullscreen),
+                Event::KeyDown { keycode: Some(key), keymod, repeat, .. } => {
+                    // Number keys with a modifier pick save state slots
+                    // instead of pressing 1-4 on the keypad
+                    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    if let (Some(n), true) = (slot(key), ctrl || shift) {
+                        if !repeat {
+                            self.hotkeys.push(if ctrl { Hotkey::SaveState(n) } else { Hotkey::LoadState(n) });
+                        }
+                    } else if let Some(&idx) = self.key_map.get(&key) {
+                        keys[idx as usize] = true;
+                    } else if let Some(&hotkey) = self.hotkey_map.get(&key) {
+                        if !repeat {
+                            self.hotkeys.push(match hotkey {
+                                Hotkey::Screenshot { .. } => Hotkey::Screenshot { native: shift },
+                                _ => hotkey,
+                            });
+                        }
+                    }
+                }
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(&idx) = self.key_map.get(&key) {
+                        keys[idx as usize] = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+}
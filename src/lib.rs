@@ -0,0 +1,32 @@
+pub mod asm;
+pub mod chip8;
+pub mod crt;
+pub mod debugger;
+pub mod decoder;
+pub mod dir;
+pub mod disasm;
+pub mod error;
+pub mod frontend;
+pub mod gdbstub;
+pub mod image;
+pub mod movie;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod scheduler;
+pub mod trace;
+
+// SDL implementations of the frontend traits
+#[cfg(feature = "sdl")]
+pub mod graphics;
+#[cfg(feature = "sdl")]
+pub mod input;
+#[cfg(feature = "sdl")]
+pub mod sound;
+
+// Terminal implementations of the frontend traits
+#[cfg(feature = "terminal")]
+pub mod terminal;
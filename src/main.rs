@@ -1,59 +1,568 @@
-pub mod chip8;
-pub mod dir;
-pub mod graphics;
-pub mod input;
+use rusty_chip8::chip8::{Chip8, Resolution, DISP_X, DISP_Y, PIXEL_SIZE};
+use rusty_chip8::crt::Crt;
+use rusty_chip8::debugger::Debugger;
+use rusty_chip8::dir;
+use rusty_chip8::error::ErrorPolicy;
+use rusty_chip8::frontend::{AudioSink, FrameSink, InputProvider};
+use rusty_chip8::gdbstub::{self, GdbAction, GdbStub};
+use rusty_chip8::graphics::{Display, ScaleMode};
+use rusty_chip8::image::{self, GifRecorder};
+use rusty_chip8::input::{self, Hotkey};
+use rusty_chip8::movie::{Movie, Player, Recorder};
+use rusty_chip8::palette::{self, Palette};
+use rusty_chip8::phosphor::{self, PersistenceMode, Phosphor};
+use rusty_chip8::sound::{self, Beeper};
+use rusty_chip8::quirks::Quirks;
+use rusty_chip8::random::RngMode;
+use rusty_chip8::rewind::{self, Rewind};
+use rusty_chip8::savestate;
+use rusty_chip8::scheduler::{self, Scheduler};
+use rusty_chip8::trace::{TraceLevel, Tracer};
 
-use chip8::Chip8;
-use graphics::Display;
-
-const SCREEN_X: u32 = 1320;
-const SCREEN_Y: u32 = 680;
+// Starting window size, the window can be resized
+const SCREEN_X: u32 = (PIXEL_SIZE * DISP_X) as u32;
+const SCREEN_Y: u32 = (PIXEL_SIZE * DISP_Y) as u32;
 const GAMES_DIR: &str = "./GAMES";
+// Default options, one per line without the leading --, e.g. "palette amber"
+const CONFIG_FILE: &str = "./rusty-chip8.conf";
+
+// Command line options
+struct Options {
+    quirks: Option<Quirks>,
+    xo_chip: bool,
+    beep_freq: f32,
+    volume: f32,
+    mute: bool,
+    ipf: u32,
+    on_error: ErrorPolicy,
+    trace: TraceLevel,
+    trace_file: Option<String>,
+    rewind_seconds: u32,
+    rewind_budget: usize,
+    record: Option<String>,
+    replay: Option<String>,
+    seed: Option<u64>,
+    rng: RngMode,
+    debug: bool,
+    gdb_port: Option<u16>,
+    capture_scale: usize,
+    palette: Palette,
+    fg: Option<palette::Rgb>,
+    bg: Option<palette::Rgb>,
+    scaling: ScaleMode,
+    fullscreen: bool,
+    persistence: PersistenceMode,
+    decay: f32,
+    crt: Crt,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: rusty-chip8 [options]");
+    eprintln!("  --quirks <{}>", Quirks::PRESET_NAMES.join("|"));
+    eprintln!("  --xochip                 enable XO-CHIP extensions");
+    eprintln!("  --beep-freq <hz>         buzzer pitch");
+    eprintln!("  --volume <0-100>         buzzer volume");
+    eprintln!("  --mute                   start with the buzzer muted");
+    eprintln!("  --ipf <n>                instructions per 60 Hz frame");
+    eprintln!("  --on-error <{}>", ErrorPolicy::NAMES.join("|"));
+    eprintln!("  --trace <{}>", TraceLevel::NAMES.join("|"));
+    eprintln!("  --trace-file <path>      write the trace here instead of stderr");
+    eprintln!("  --rewind <seconds>       how far Backspace can rewind, 0 turns it off");
+    eprintln!("  --rewind-budget <MiB>    memory the rewind buffer may use");
+    eprintln!("  --record <path>          record an input movie");
+    eprintln!("  --replay <path>          play back an input movie");
+    eprintln!("  --seed <n>               seed for Cxkk's random numbers");
+    eprintln!("  --rng <{}>", RngMode::NAMES.join("|"));
+    eprintln!("  --debug                  start in the debugger");
+    eprintln!("  --gdb <port>             wait for gdb to connect on this port");
+    eprintln!("  --capture-scale <n>      pixels per CHIP-8 pixel in screenshots and GIFs");
+    eprintln!("  --palette <{}>", Palette::NAMES.join("|"));
+    eprintln!("  --fg <RRGGBB>            custom foreground colour");
+    eprintln!("  --bg <RRGGBB>            custom background colour");
+    eprintln!("  --scaling <{}>", ScaleMode::NAMES.join("|"));
+    eprintln!("  --fullscreen             start fullscreen");
+    eprintln!("  --persistence <{}>", PersistenceMode::NAMES.join("|"));
+    eprintln!("  --decay <0-1>            brightness a pixel keeps per frame with blend");
+    eprintln!("  --scanlines <0-1>        CRT scanline strength, 0 is off");
+    eprintln!("  --grid <0-1>             darkness of the gaps between pixels, 0 is off");
+    eprintln!("  --bloom <0-1>            glow around lit pixels, 0 is off");
+    eprintln!("  --curvature <0-1>        bulge of the screen, 0 is off");
+    eprintln!("Options can also be put in {}, one per line without the --", CONFIG_FILE);
+    std::process::exit(1);
+}
+
+// Options from CONFIG_FILE, as if they were given on the command line
+fn config_args() -> Vec<String> {
+    let text = match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    let mut args = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        if let Some(name) = words.next() {
+            args.push(format!("--{}", name));
+            args.extend(words.map(String::from));
+        }
+    }
+    args
+}
+
+// An effect strength from 0 to 1
+fn strength(arg: Option<String>) -> f32 {
+    arg.and_then(|s| s.parse().ok())
+        .filter(|s| (0.0..=1.0).contains(s))
+        .unwrap_or_else(|| usage())
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        quirks: None,
+        xo_chip: false,
+        beep_freq: sound::DEFAULT_FREQUENCY,
+        volume: sound::DEFAULT_VOLUME,
+        mute: false,
+        ipf: scheduler::DEFAULT_IPF,
+        on_error: ErrorPolicy::default(),
+        trace: TraceLevel::Off,
+        trace_file: None,
+        rewind_seconds: rewind::DEFAULT_SECONDS,
+        rewind_budget: rewind::DEFAULT_BUDGET,
+        record: None,
+        replay: None,
+        seed: None,
+        rng: RngMode::default(),
+        debug: false,
+        gdb_port: None,
+        capture_scale: image::DEFAULT_CAPTURE_SCALE,
+        palette: Palette::default(),
+        fg: None,
+        bg: None,
+        scaling: ScaleMode::default(),
+        fullscreen: false,
+        persistence: PersistenceMode::default(),
+        decay: phosphor::DEFAULT_DECAY,
+        crt: Crt::default(),
+    };
+    // The command line comes last so it wins over the config file
+    let mut args = config_args().into_iter().chain(std::env::args().skip(1));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                opts.quirks = Some(Quirks::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown quirks preset: {}", name);
+                    usage()
+                }));
+            }
+            "--xochip" => opts.xo_chip = true,
+            "--beep-freq" => {
+                opts.beep_freq = args.next().and_then(|hz| hz.parse().ok())
+                    .unwrap_or_else(|| usage());
+            }
+            "--volume" => {
+                let percent: f32 = args.next().and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage());
+                opts.volume = percent / 100.0;
+            }
+            "--mute" => opts.mute = true,
+            "--ipf" => {
+                opts.ipf = args.next().and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--on-error" => {
+                opts.on_error = args.next().and_then(|p| ErrorPolicy::from_name(&p))
+                    .unwrap_or_else(|| usage());
+            }
+            "--trace" => {
+                opts.trace = args.next().and_then(|l| TraceLevel::from_name(&l))
+                    .unwrap_or_else(|| usage());
+            }
+            "--trace-file" => opts.trace_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--rewind" => {
+                opts.rewind_seconds = args.next().and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage());
+            }
+            "--rewind-budget" => {
+                let mib: usize = args.next().and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage());
+                opts.rewind_budget = mib * 1024 * 1024;
+            }
+            "--record" => opts.record = Some(args.next().unwrap_or_else(|| usage())),
+            "--replay" => opts.replay = Some(args.next().unwrap_or_else(|| usage())),
+            "--seed" => opts.seed = Some(args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage())),
+            "--debug" => opts.debug = true,
+            "--capture-scale" => {
+                opts.capture_scale = args.next().and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage());
+            }
+            "--palette" => {
+                opts.palette = args.next().and_then(|p| Palette::from_name(&p))
+                    .unwrap_or_else(|| usage());
+            }
+            "--fg" => opts.fg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--bg" => opts.bg = Some(args.next().and_then(|c| palette::parse_rgb(&c)).unwrap_or_else(|| usage())),
+            "--scaling" => {
+                opts.scaling = args.next().and_then(|m| ScaleMode::from_name(&m))
+                    .unwrap_or_else(|| usage());
+            }
+            "--fullscreen" => opts.fullscreen = true,
+            "--persistence" => {
+                opts.persistence = args.next().and_then(|m| PersistenceMode::from_name(&m))
+                    .unwrap_or_else(|| usage());
+            }
+            "--decay" => {
+                opts.decay = args.next().and_then(|d| d.parse().ok())
+                    .filter(|d| (0.0..=1.0).contains(d))
+                    .unwrap_or_else(|| usage());
+            }
+            "--scanlines" => opts.crt.scanlines = strength(args.next()),
+            "--grid" => opts.crt.grid = strength(args.next()),
+            "--bloom" => opts.crt.bloom = strength(args.next()),
+            "--curvature" => opts.crt.curvature = strength(args.next()),
+            "--gdb" => opts.gdb_port = Some(args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage())),
+            "--rng" => {
+                opts.rng = args.next().and_then(|m| RngMode::from_name(&m))
+                    .unwrap_or_else(|| usage());
+            }
+            _ => usage(),
+        }
+    }
+    opts
+}
+
+// Adds the screen to the GIF being recorded, if any. A write error stops
+// the recording rather than the game.
+fn record_gif_frame(gif: &mut Option<GifRecorder>, c8: &Chip8) {
+    if let Some(recorder) = gif.as_mut() {
+        if let Err(e) = recorder.capture(c8.screen(), c8.disp_x(), c8.disp_y()) {
+            eprintln!("GIF recording stopped: {}", e);
+            *gif = None;
+        }
+    }
+}
 
 pub fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
+    let mut opts = parse_args();
+    if opts.record.is_some() && opts.replay.is_some() {
+        eprintln!("Can't record and replay at the same time");
+        usage();
+    }
+    // A movie only replays correctly with the settings it was recorded with
+    let replay = opts.replay.as_ref().map(|path| {
+        let movie = Movie::load(path).unwrap_or_else(|e| {
+            eprintln!("Could not load movie {}: {}", path, e);
+            std::process::exit(1);
+        });
+        opts.xo_chip = movie.xo_chip;
+        opts.quirks = Some(movie.quirks);
+        opts.ipf = movie.ipf;
+        opts.seed = Some(movie.seed);
+        opts.rng = movie.rng;
+        movie
+    });
     let selected_game = dir::Navigator::select_game(GAMES_DIR);
     
-    let mut c8: Chip8 = Chip8::new();
-    let input_handler = input::Handler::new();
+    // XO-CHIP ROMs expect Octo's quirks unless told otherwise
+    let quirks = opts.quirks.unwrap_or(if opts.xo_chip { Quirks::XO_CHIP } else { Quirks::default() });
+    let mut c8: Chip8 = Chip8::with_quirks(quirks);
+    c8.set_xo_chip(opts.xo_chip);
+    c8.error_policy = opts.on_error;
+    // Printed so a run can be reproduced with --seed
+    let seed = opts.seed.unwrap_or_else(rand::random);
+    println!("RNG seed: {}", seed);
+    c8.rng = opts.rng.source(seed);
+    c8.tracer = match &opts.trace_file {
+        Some(path) => Tracer::to_file(opts.trace, path).unwrap_or_else(|e| {
+            eprintln!("Could not open trace file {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => Tracer::stderr(opts.trace),
+    };
     c8.load_font();
-    c8.load_rom(selected_game.to_string());
+    println!("Loading game: {}", selected_game);
+    match c8.load_rom(selected_game.to_string()) {
+        Ok(size) => println!("{} bytes loaded into memory", size),
+        Err(e) => {
+            eprintln!("Could not load {}: {}", selected_game, e);
+            std::process::exit(1);
+        }
+    }
+    if c8.resolution() == Resolution::Tall {
+        println!("HIRES CHIP-8 program detected, using 64x64 display");
+    }
 
     let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-
-    let window = video_subsystem.window("Rusty CHIP-8", 
-        SCREEN_X,
-        SCREEN_Y)
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas()
-        .accelerated()
-        // .present_vsync()
-        .build()
-        .unwrap();
-
-    Display::clear(&mut canvas);
-    let mut num_cycles = 0;
-    loop {
-        // let frame = std::time::Duration::from_micros(1000);
-        // let now = std::time::Instant::now();
-        // std::thread::sleep(frame);
-        c8.cycle(&input_handler, &sdl_context);
-        println!("Cycles: {}", num_cycles);
-        // let mut s=String::new();
-        // std::io::stdin().read_line(&mut s).ok();
-        c8.print_registers();
-        if input_handler.set_chip8_keys(&mut c8, &sdl_context) {
-            break;
-        }
-        // input_handler.print_chip8_keys(&c8);
-        Display::render_gfx(&mut c8, &mut canvas);
-        c8.count_dt();
-        num_cycles += 1;        
-        // ::std::thread::sleep(std::time::Duration::new(0, 1_000_000_000u32 / 30));
-        // ::std::thread::sleep(std::time::Duration::new(0, 1_000_000_000u32 / 60));
+    let mut display = Display::new(&sdl_context, "Rusty CHIP-8", SCREEN_X, SCREEN_Y);
+    let mut input_handler = input::Handler::new(&sdl_context);
+    display.palette = opts.palette.recolor(opts.fg, opts.bg);
+    display.scale_mode = opts.scaling;
+    display.phosphor = Phosphor::new(opts.persistence, opts.decay);
+    display.crt = opts.crt.clone();
+    display.set_fullscreen(opts.fullscreen);
+    let mut beeper = Beeper::new(&sdl_context, opts.beep_freq, opts.volume);
+    beeper.muted = opts.mute;
+
+    let mut ipf = opts.ipf;
+    let mut paused = false;
+    let mut scheduler = Scheduler::new();
+    let mut rewind = Rewind::new(opts.rewind_seconds, opts.rewind_budget);
+    let mut recorder = opts.record.as_ref().map(|_| Recorder::new(&mut c8, opts.rng, seed, ipf));
+    let mut player = replay.map(|movie| {
+        if let Err(e) = movie.prepare(&mut c8, ipf) {
+            eprintln!("Can't replay: {}", e);
+            std::process::exit(1);
+        }
+        Player::new(movie)
+    });
+    let mut debugger = Debugger::new();
+    let mut gif: Option<GifRecorder> = None;
+    let mut break_in = opts.debug;
+    // With gdb attached, stops go to it instead of the command line
+    // debugger. It is waiting for the machine to stop, so start stopped.
+    let mut gdb = opts.gdb_port.map(|port| GdbStub::listen(port).unwrap_or_else(|e| {
+        eprintln!("Could not listen for gdb on port {}: {}", port, e);
+        std::process::exit(1);
+    }));
+    let mut gdb_stop = gdb.as_ref().map(|_| gdbstub::SIGTRAP);
+    'running: loop {
+        if let Some(stub) = gdb.as_mut() {
+            if gdb_stop.is_none() && stub.interrupted() {
+                gdb_stop = Some(gdbstub::SIGINT);
+            }
+            if let Some(signal) = gdb_stop.take() {
+                beeper.set_beeping(false);
+                display.present(c8.screen(), c8.disp_x(), c8.disp_y());
+                match stub.serve(&mut c8, &mut debugger, ipf, signal) {
+                    Ok(GdbAction::Continue) => {}
+                    Ok(GdbAction::Detach) => {
+                        println!("gdb detached");
+                        gdb = None;
+                    }
+                    Ok(GdbAction::Kill) => break 'running,
+                    Err(e) => {
+                        eprintln!("Lost the gdb connection: {}", e);
+                        debugger.breakpoints.clear();
+                        gdb = None;
+                    }
+                }
+                scheduler.reset();
+            }
+        }
+        if break_in {
+            break_in = false;
+            beeper.set_beeping(false);
+            display.present(c8.screen(), c8.disp_x(), c8.disp_y());
+            if debugger.repl(&mut c8, ipf, &mut display) {
+                break 'running;
+            }
+            // Don't try to catch up on the time spent in the debugger
+            scheduler.reset();
+        }
+        let frames_due = scheduler.frames_due();
+        // Fading pixels change the picture even when the ROM draws nothing
+        let mut faded = false;
+        // Anything that changes the machine behind the movie's back would
+        // make it desync, so rewinding, loading and speed changes are off
+        let movie_active = recorder.is_some() || player.is_some();
+        if input_handler.rewinding && !movie_active {
+            if input_handler.poll_keys(&mut c8.keys) {
+                break 'running;
+            }
+            beeper.set_beeping(false);
+            for _ in 0..frames_due {
+                if let Some(state) = rewind.step_back() {
+                    // Keep the keys that are actually held right now
+                    let keys = c8.keys;
+                    c8.load_state(state).expect("rewind state is always for this ROM");
+                    c8.keys = keys;
+                    faded |= display.phosphor.step(c8.screen(), c8.disp_x(), c8.disp_y());
+                    record_gif_frame(&mut gif, &c8);
+                }
+            }
+        } else if paused {
+            // Keep the window responsive while nothing is running
+            if input_handler.poll_keys(&mut c8.keys) {
+                break 'running;
+            }
+        } else {
+            for _ in 0..frames_due {
+                let result = {
+                    let mut recording;
+                    let mut replaying;
+                    let input: &mut dyn InputProvider = if let Some(recorder) = recorder.as_mut() {
+                        recording = recorder.input(&mut input_handler);
+                        &mut recording
+                    } else if let Some(player) = player.as_mut() {
+                        replaying = player.input(&mut input_handler);
+                        &mut replaying
+                    } else {
+                        &mut input_handler
+                    };
+                    // Breakpoints and watchpoints need checking between instructions
+                    if debugger.armed() {
+                        debugger.run_frame(&mut c8, ipf, input, &mut beeper)
+                    } else {
+                        c8.run_frame(ipf, input, &mut beeper)
+                    }
+                };
+                match result {
+                    Ok(quit) => {
+                        if quit {
+                            break 'running;
+                        }
+                        let stop = debugger.take_stop();
+                        // A frame the debugger stopped in is finished after it
+                        if !debugger.mid_frame() {
+                            if let Some(recorder) = recorder.as_mut() {
+                                recorder.end_frame(&c8);
+                            }
+                            faded |= display.phosphor.step(c8.screen(), c8.disp_x(), c8.disp_y());
+                            record_gif_frame(&mut gif, &c8);
+                            if player.as_ref().is_some_and(|p| p.finished()) {
+                                let player = player.take().unwrap();
+                                match player.verify(&c8) {
+                                    Ok(()) => println!("Replay finished in sync after {} frames", player.movie.frames),
+                                    Err(hash) => eprintln!("Replay desynced: final state hash is {:016X}, expected {:016X}",
+                                                           hash, player.movie.final_hash),
+                                }
+                            }
+                            if c8.exit_flag {
+                                break 'running;
+                            }
+                            if !movie_active {
+                                rewind.push(c8.save_state());
+                            }
+                        }
+                        if let Some(reason) = stop {
+                            if gdb.is_some() {
+                                gdb_stop = Some(gdbstub::SIGTRAP);
+                            } else {
+                                println!("{}", reason);
+                                break_in = true;
+                            }
+                            break;
+                        }
+                    }
+                    Err(e) if c8.error_policy == ErrorPolicy::Trap => {
+                        // The PC is still on the faulting instruction
+                        eprintln!("Trapped: {}", e);
+                        if gdb.is_some() {
+                            gdb_stop = Some(gdbstub::signal_for(&e));
+                        } else {
+                            break_in = true;
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("Halted: {}", e);
+                        c8.print_registers();
+                        break 'running;
+                    }
+                }
+            }
+        }
+        for hotkey in input_handler.take_hotkeys() {
+            match hotkey {
+                Hotkey::CycleTrace => {
+                    c8.tracer.level = c8.tracer.level.next();
+                    c8.tracer.flush();
+                    println!("Trace level: {}", c8.tracer.level);
+                }
+                Hotkey::Pause => {
+                    paused = !paused;
+                    beeper.set_beeping(false);
+                    println!("{}", if paused { "Paused" } else { "Resumed" });
+                }
+                Hotkey::ToggleMute => beeper.toggle_mute(),
+                Hotkey::ToggleFullscreen => {
+                    let on = !display.is_fullscreen();
+                    display.set_fullscreen(on);
+                    c8.draw_flag = true;
+                }
+                Hotkey::CyclePalette => {
+                    display.palette = display.palette.next();
+                    println!("Palette: {}", display.palette);
+                    c8.draw_flag = true;
+                }
+                Hotkey::Debug => break_in = true,
+                Hotkey::SpeedUp | Hotkey::SpeedDown | Hotkey::LoadState(_) if movie_active => {
+                    println!("Not available while a movie is recording or playing");
+                }
+                Hotkey::SpeedUp => {
+                    ipf += 1;
+                    println!("{} instructions per frame", ipf);
+                }
+                Hotkey::SpeedDown => {
+                    ipf = ipf.saturating_sub(1).max(1);
+                    println!("{} instructions per frame", ipf);
+                }
+                Hotkey::SaveState(slot) => match savestate::save_slot(&c8, &selected_game, slot) {
+                    Ok(path) => println!("Saved slot {} to {}", slot, path.display()),
+                    Err(e) => eprintln!("Could not save slot {}: {}", slot, e),
+                },
+                Hotkey::LoadState(slot) => match savestate::load_slot(&mut c8, &selected_game, slot) {
+                    Ok(path) => {
+                        println!("Loaded slot {} from {}", slot, path.display());
+                        // Rewinding past a load would jump between timelines
+                        rewind.clear();
+                    }
+                    Err(e) => eprintln!("Could not load slot {}: {}", slot, e),
+                },
+                Hotkey::Screenshot { native } => {
+                    let scale = if native { 1 } else { opts.capture_scale };
+                    let result = image::capture_path(&selected_game, "png").and_then(|path| {
+                        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                        image::write_png(&mut file, c8.screen(), c8.disp_x(), c8.disp_y(), &display.palette.colors, scale)?;
+                        Ok(path)
+                    });
+                    match result {
+                        Ok(path) => println!("Saved screenshot to {}", path.display()),
+                        Err(e) => eprintln!("Could not save screenshot: {}", e),
+                    }
+                }
+                Hotkey::ToggleGif => match gif.take() {
+                    Some(recorder) => match recorder.finish() {
+                        Ok(frames) => println!("Stopped recording GIF after {} frames", frames),
+                        Err(e) => eprintln!("Could not finish GIF: {}", e),
+                    },
+                    None => {
+                        let result = image::capture_path(&selected_game, "gif").and_then(|path| {
+                            let recorder = GifRecorder::create(&path, c8.disp_x(), c8.disp_y(), &display.palette.colors,
+                                                               opts.capture_scale)?;
+                            Ok((path, recorder))
+                        });
+                        match result {
+                            Ok((path, recorder)) => {
+                                println!("Recording GIF to {}", path.display());
+                                gif = Some(recorder);
+                            }
+                            Err(e) => eprintln!("Could not start GIF: {}", e),
+                        }
+                    }
+                },
+            }
+        }
+        if c8.draw_flag || std::mem::take(&mut input_handler.redraw) || faded {
+            c8.draw_flag = false;
+            display.present(c8.screen(), c8.disp_x(), c8.disp_y());
+        }
+        scheduler.wait();
+    }
+    if let Some(Err(e)) = gif.map(|recorder| recorder.finish()) {
+        eprintln!("Could not finish GIF: {}", e);
+    }
+    if let (Some(recorder), Some(path)) = (recorder, &opts.record) {
+        let movie = recorder.finish();
+        match movie.save(path) {
+            Ok(()) => println!("Recorded {} frames to {}", movie.frames, path),
+            Err(e) => eprintln!("Could not save movie {}: {}", path, e),
+        }
     }
+    c8.tracer.flush();
 }
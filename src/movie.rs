@@ -0,0 +1,441 @@
+// Input movies: every keypad press and release of a run, by frame number,
+// plus everything else the run depended on (ROM, RNG seed, speed, quirks).
+// Replaying one feeds the same keys through InputProvider::poll_keys on the
+// same frames, so the machine must end up in exactly the same state. The
+// hash of that final state is stored to catch desyncs.
+//
+// Movies are plain text:
+//   rusty-chip8 movie 1
+//   rom <hash>
+//   seed <seed>
+//   rng uniform|vip
+//   ipf <n>
+//   xochip <0|1>
+//   quirks <shift_vy> <load_store_i> <jump_vx> <vf_reset> <clip_sprites>
+//                                (flags are 0 or 1; load_store_i is 0 keep,
+//                                1 add x + 1, 2 add x)
+//   <frame> <key> down|up        (one line per event)
+//   end <frames> <final state hash>
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::chip8::{Chip8, NUM_KEYS};
+use crate::frontend::InputProvider;
+use crate::quirks::{LoadStoreI, Quirks};
+use crate::random::RngMode;
+use crate::savestate::rom_hash;
+
+const HEADER: &str = "rusty-chip8 movie 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub rng: RngMode,
+    pub ipf: u32,
+    pub xo_chip: bool,
+    pub quirks: Quirks,
+    pub events: Vec<KeyEvent>,
+    // Length of the run in frames
+    pub frames: u64,
+    // state_hash() after the last frame
+    pub final_hash: u64,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+// Fingerprint of the whole machine
+pub fn state_hash(c8: &Chip8) -> u64 {
+    rom_hash(&c8.save_state())
+}
+
+impl Movie {
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let q = &self.quirks;
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "rom {:016X}", self.rom_hash)?;
+        writeln!(out, "seed {}", self.seed)?;
+        writeln!(out, "rng {}", self.rng)?;
+        writeln!(out, "ipf {}", self.ipf)?;
+        writeln!(out, "xochip {}", self.xo_chip as u8)?;
+        let load_store_i = match q.load_store_i {
+            LoadStoreI::Keep => 0,
+            LoadStoreI::AddXPlusOne => 1,
+            LoadStoreI::AddX => 2,
+        };
+        writeln!(out, "quirks {} {} {} {} {}", q.shift_vy as u8, load_store_i,
+                 q.jump_vx as u8, q.vf_reset as u8, q.clip_sprites as u8)?;
+        for e in self.events.iter() {
+            writeln!(out, "{} {:X} {}", e.frame, e.key, if e.pressed { "down" } else { "up" })?;
+        }
+        writeln!(out, "end {} {:016X}", self.frames, self.final_hash)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &str) -> Result<Movie, MovieError> {
+        Movie::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie {
+            rom_hash: 0, seed: 0, rng: RngMode::default(), ipf: 0, xo_chip: false, quirks: Quirks::default(),
+            events: Vec::new(), frames: 0, final_hash: 0,
+        };
+        let mut ended = false;
+        for (n, line) in text.lines().enumerate() {
+            let err = |message: &str| MovieError::Parse { line: n + 1, message: message.to_string() };
+            if n == 0 {
+                if line != HEADER {
+                    return Err(err("not a rusty-chip8 movie"));
+                }
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let dec = |i: usize| words.get(i).and_then(|w| w.parse::<u64>().ok()).ok_or_else(|| err("bad number"));
+            let hex = |i: usize| words.get(i).and_then(|w| u64::from_str_radix(w, 16).ok()).ok_or_else(|| err("bad number"));
+            let flag = |i: usize| match words.get(i) {
+                Some(&"0") => Ok(false),
+                Some(&"1") => Ok(true),
+                _ => Err(err("expected 0 or 1")),
+            };
+            match words.first() {
+                None => {}
+                Some(_) if ended => return Err(err("text after 'end'")),
+                Some(&"rom") => movie.rom_hash = hex(1)?,
+                Some(&"seed") => movie.seed = dec(1)?,
+                Some(&"rng") => {
+                    movie.rng = words.get(1).and_then(|w| RngMode::from_name(w))
+                        .ok_or_else(|| err("unknown RNG mode"))?;
+                }
+                Some(&"ipf") => movie.ipf = dec(1)? as u32,
+                Some(&"xochip") => movie.xo_chip = flag(1)?,
+                Some(&"quirks") => movie.quirks = Quirks {
+                    shift_vy: flag(1)?,
+                    load_store_i: match words.get(2) {
+                        Some(&"0") => LoadStoreI::Keep,
+                        Some(&"1") => LoadStoreI::AddXPlusOne,
+                        Some(&"2") => LoadStoreI::AddX,
+                        _ => return Err(err("expected 0, 1 or 2")),
+                    },
+                    jump_vx: flag(3)?,
+                    vf_reset: flag(4)?,
+                    clip_sprites: flag(5)?,
+                },
+                Some(&"end") => {
+                    movie.frames = dec(1)?;
+                    movie.final_hash = hex(2)?;
+                    ended = true;
+                }
+                Some(_) => {
+                    let key = hex(1)?;
+                    if key as usize >= NUM_KEYS {
+                        return Err(err("bad key"));
+                    }
+                    let pressed = match words.get(2) {
+                        Some(&"down") => true,
+                        Some(&"up") => false,
+                        _ => return Err(err("expected 'down' or 'up'")),
+                    };
+                    movie.events.push(KeyEvent { frame: dec(0)?, key: key as u8, pressed });
+                }
+            }
+        }
+        if !ended {
+            return Err(MovieError::Parse { line: text.lines().count(), message: "missing 'end'".to_string() });
+        }
+        Ok(movie)
+    }
+
+    // Checks a machine with the ROM already loaded, running at `ipf`, is
+    // set up the way this movie was recorded, then seeds its RNG. Fails if
+    // the ROM, quirks or speed differ, the run would desync otherwise.
+    pub fn prepare(&self, c8: &mut Chip8, ipf: u32) -> Result<(), String> {
+        if c8.rom_hash() != self.rom_hash {
+            return Err(format!("movie was recorded on a different ROM (hash {:016X}, loaded {:016X})",
+                               self.rom_hash, c8.rom_hash()));
+        }
+        if c8.xo_chip != self.xo_chip {
+            return Err(format!("movie was recorded with XO-CHIP {}", if self.xo_chip { "on" } else { "off" }));
+        }
+        if c8.quirks != self.quirks {
+            return Err("movie was recorded with different quirks".to_string());
+        }
+        if ipf != self.ipf {
+            return Err(format!("movie was recorded at {} instructions per frame, not {}", self.ipf, ipf));
+        }
+        c8.rng = self.rng.source(self.seed);
+        Ok(())
+    }
+}
+
+// Records the keys of a run. Wrap the real input with input() for every
+// frame, then call end_frame() once the frame has run.
+pub struct Recorder {
+    movie: Movie,
+    last: [bool; NUM_KEYS],
+}
+
+// The real input with the recorder watching it
+pub struct Recording<'a> {
+    recorder: &'a mut Recorder,
+    inner: &'a mut dyn InputProvider,
+}
+
+impl Recorder {
+    // Gives the machine a freshly seeded RNG so the run can be replayed
+    pub fn new(c8: &mut Chip8, rng: RngMode, seed: u64, ipf: u32) -> Self {
+        c8.rng = rng.source(seed);
+        let movie = Movie {
+            rom_hash: c8.rom_hash(), seed, rng, ipf, xo_chip: c8.xo_chip, quirks: c8.quirks,
+            events: Vec::new(), frames: 0, final_hash: state_hash(c8),
+        };
+        Self { movie, last: [false; NUM_KEYS] }
+    }
+
+    pub fn input<'a>(&'a mut self, inner: &'a mut dyn InputProvider) -> Recording<'a> {
+        Recording { recorder: self, inner }
+    }
+
+    pub fn end_frame(&mut self, c8: &Chip8) {
+        self.movie.frames += 1;
+        self.movie.final_hash = state_hash(c8);
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+impl InputProvider for Recording<'_> {
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+        let quit = self.inner.poll_keys(keys);
+        let rec = &mut *self.recorder;
+        for (key, (&now, &before)) in keys.iter().zip(rec.last.iter()).enumerate() {
+            if now != before {
+                rec.movie.events.push(KeyEvent { frame: rec.movie.frames, key: key as u8, pressed: now });
+            }
+        }
+        rec.last = *keys;
+        quit
+    }
+}
+
+// Plays a movie back. Like Recorder, wrap the real input with input() so
+// the window keeps working, but the keypad state comes from the movie.
+pub struct Player {
+    pub movie: Movie,
+    next: usize,
+    frame: u64,
+    keys: [bool; NUM_KEYS],
+}
+
+pub struct Replaying<'a> {
+    player: &'a mut Player,
+    inner: &'a mut dyn InputProvider,
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, next: 0, frame: 0, keys: [false; NUM_KEYS] }
+    }
+
+    pub fn input<'a>(&'a mut self, inner: &'a mut dyn InputProvider) -> Replaying<'a> {
+        Replaying { player: self, inner }
+    }
+
+    // Every frame of the movie has been played
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    // Once finished, checks the machine ended up where the recording did.
+    // Returns the hash it ended up with instead on a desync.
+    pub fn verify(&self, c8: &Chip8) -> Result<(), u64> {
+        let hash = state_hash(c8);
+        if hash == self.movie.final_hash { Ok(()) } else { Err(hash) }
+    }
+}
+
+impl InputProvider for Replaying<'_> {
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+        // Still poll so the window can be closed, but ignore its keypad
+        let mut live = *keys;
+        let quit = self.inner.poll_keys(&mut live);
+        let p = &mut *self.player;
+        while let Some(event) = p.movie.events.get(p.next) {
+            if event.frame > p.frame {
+                break;
+            }
+            p.keys[event.key as usize] = event.pressed;
+            p.next += 1;
+        }
+        *keys = p.keys;
+        p.frame += 1;
+        quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::AudioCapture;
+
+    // Waits for a key, then adds a random number to it, forever
+    const PROGRAM: [u16; 4] = [0xF00A, 0xC1FF, 0x8014, 0x1200];
+    const IPF: u32 = 10;
+
+    fn machine() -> Chip8 {
+        let mut c8 = Chip8::new();
+        let rom: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_be_bytes()).collect();
+        c8.load_rom_bytes(&rom).unwrap();
+        c8
+    }
+
+    // Presses a different key for a few frames out of every seven
+    struct Script {
+        frame: u64,
+    }
+
+    impl InputProvider for Script {
+        fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+            *keys = [false; NUM_KEYS];
+            if self.frame % 7 < 3 {
+                keys[(self.frame / 7) as usize % NUM_KEYS] = true;
+            }
+            self.frame += 1;
+            false
+        }
+    }
+
+    fn record(frames: u64) -> (Movie, Chip8) {
+        let mut c8 = machine();
+        let mut recorder = Recorder::new(&mut c8, RngMode::Uniform, 1234, IPF);
+        let mut script = Script { frame: 0 };
+        for _ in 0..frames {
+            c8.run_frame(IPF, &mut recorder.input(&mut script), &mut AudioCapture::default()).unwrap();
+            recorder.end_frame(&c8);
+        }
+        (recorder.finish(), c8)
+    }
+
+    // Replays on a fresh machine, with the live keypad mashing a key that
+    // should be ignored
+    fn replay(movie: Movie) -> Result<(), u64> {
+        let mut c8 = machine();
+        movie.prepare(&mut c8, IPF).unwrap();
+        let mut player = Player::new(movie);
+        let mut live = Script { frame: 3 };
+        while !player.finished() {
+            c8.run_frame(IPF, &mut player.input(&mut live), &mut AudioCapture::default()).unwrap();
+        }
+        player.verify(&c8)
+    }
+
+    fn text(movie: &Movie) -> String {
+        let mut out = Vec::new();
+        movie.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_and_parse_round_trip() {
+        let (mut movie, _) = record(30);
+        movie.rng = RngMode::Vip;
+        movie.xo_chip = true;
+        movie.quirks = Quirks::CHIP_48;
+        let written = text(&movie);
+        let parsed = Movie::parse(&written).unwrap();
+        assert_eq!(text(&parsed), written);
+        assert_eq!((parsed.rom_hash, parsed.seed, parsed.rng, parsed.ipf, parsed.xo_chip, parsed.quirks),
+                   (movie.rom_hash, movie.seed, movie.rng, movie.ipf, movie.xo_chip, movie.quirks));
+        assert_eq!((parsed.frames, parsed.final_hash), (30, movie.final_hash));
+        assert_eq!(parsed.events, movie.events);
+        assert_eq!(parsed.events[..2], [KeyEvent { frame: 0, key: 0, pressed: true },
+                                        KeyEvent { frame: 3, key: 0, pressed: false }]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let good = text(&record(0).0);
+        let line = |text: &str| match Movie::parse(text) {
+            Err(MovieError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other.map(|m| m.frames)),
+        };
+        assert_eq!(line("rusty-chip8 movie 2\n"), 1);
+        assert_eq!(line(&good.replace("\nend", "\n0 G down\nend")), 8);
+        assert_eq!(line(&good.replace("\nend", "\n0 1 sideways\nend")), 8);
+        assert_eq!(line(&good.replace("\nquirks ", "\nquirks 0 3 ")), 7);
+        assert_eq!(line(&good.replace("\nend", "\nnope")), 8);
+        assert_eq!(line(&format!("{}0 1 down\n", good)), 9);
+    }
+
+    #[test]
+    fn replay_reaches_the_recorded_state() {
+        let (movie, recorded) = record(120);
+        assert!(movie.events.len() > 20);
+        assert_eq!(movie.final_hash, state_hash(&recorded));
+        let movie = Movie::parse(&text(&movie)).unwrap();
+        assert_eq!(replay(movie.clone()), Ok(()));
+
+        // A different seed or a lost key press both desync
+        let mut reseeded = movie.clone();
+        reseeded.seed += 1;
+        assert!(replay(reseeded).is_err());
+        let mut dropped = movie;
+        dropped.events.remove(4);
+        assert!(replay(dropped).is_err());
+    }
+
+    #[test]
+    fn mismatched_setups_are_rejected() {
+        let (movie, _) = record(10);
+        let mut c8 = machine();
+        assert!(movie.prepare(&mut c8, IPF + 1).unwrap_err().contains("instructions per frame"));
+        c8.quirks.jump_vx = !c8.quirks.jump_vx;
+        assert!(movie.prepare(&mut c8, IPF).unwrap_err().contains("quirks"));
+
+        let mut c8 = machine();
+        c8.set_xo_chip(true);
+        assert!(movie.prepare(&mut c8, IPF).unwrap_err().contains("XO-CHIP"));
+
+        let mut c8 = Chip8::new();
+        c8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        assert!(movie.prepare(&mut c8, IPF).unwrap_err().contains("different ROM"));
+
+        assert_eq!(movie.prepare(&mut machine(), IPF), Ok(()));
+    }
+}
@@ -0,0 +1,99 @@
+// Display colours. A palette gives the colour of each gfx value: 0 is the
+// background, 1 the foreground, 2 and 3 only show up when an XO-CHIP ROM
+// draws on the second bitplane.
+use std::fmt;
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub const CLASSIC: Palette = Palette {
+        name: "classic",
+        colors: [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)],
+    };
+    // P1 phosphor of old monochrome monitors
+    pub const GREEN: Palette = Palette {
+        name: "green",
+        colors: [(5, 20, 5), (51, 255, 102), (34, 170, 68), (20, 95, 40)],
+    };
+    pub const AMBER: Palette = Palette {
+        name: "amber",
+        colors: [(20, 10, 0), (255, 176, 0), (180, 120, 0), (100, 65, 0)],
+    };
+    // Original Game Boy screen
+    pub const LCD: Palette = Palette {
+        name: "lcd",
+        colors: [(155, 188, 15), (15, 56, 15), (48, 98, 48), (139, 172, 15)],
+    };
+    // Bitplanes in colours that can't be mistaken for each other
+    pub const HIGH_CONTRAST: Palette = Palette {
+        name: "high-contrast",
+        colors: [(0, 0, 0), (255, 255, 0), (0, 255, 255), (255, 0, 255)],
+    };
+    // Okabe-Ito colours, told apart with any kind of colour blindness
+    pub const COLORBLIND: Palette = Palette {
+        name: "colorblind",
+        colors: [(0, 0, 0), (230, 159, 0), (86, 180, 233), (240, 228, 66)],
+    };
+
+    pub const PRESETS: [Palette; 6] = [
+        Palette::CLASSIC, Palette::GREEN, Palette::AMBER, Palette::LCD, Palette::HIGH_CONTRAST, Palette::COLORBLIND,
+    ];
+    // Names accepted on the command line
+    pub const NAMES: [&'static str; 6] = ["classic", "green", "amber", "lcd", "high-contrast", "colorblind"];
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let name = name.to_lowercase();
+        Palette::PRESETS.iter().find(|p| p.name == name).copied()
+    }
+
+    // Swaps in custom foreground and/or background colours. The bitplane
+    // colours are blended between the two, like the classic greys.
+    pub fn recolor(self, fg: Option<Rgb>, bg: Option<Rgb>) -> Palette {
+        if fg.is_none() && bg.is_none() {
+            return self;
+        }
+        let (fg, bg) = (fg.unwrap_or(self.colors[1]), bg.unwrap_or(self.colors[0]));
+        let mix = |n: u16| {
+            let channel = |f: u8, b: u8| ((f as u16 * n + b as u16 * (3 - n)) / 3) as u8;
+            (channel(fg.0, bg.0), channel(fg.1, bg.1), channel(fg.2, bg.2))
+        };
+        Palette { name: "custom", colors: [bg, fg, mix(2), mix(1)] }
+    }
+
+    // The preset after this one, for cycling with a hotkey
+    pub fn next(&self) -> Palette {
+        let current = Palette::PRESETS.iter().position(|p| p.name == self.name);
+        match current {
+            Some(n) => Palette::PRESETS[(n + 1) % Palette::PRESETS.len()],
+            None => Palette::PRESETS[0],
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::CLASSIC
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// "RRGGBB", with or without a leading #
+pub fn parse_rgb(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
@@ -0,0 +1,250 @@
+// Phosphor persistence, to tame the flicker of games that erase and redraw
+// their sprites with XOR every frame. Works on the colours that are about
+// to be shown; gfx itself is never touched, so the machine (and save states,
+// movies and screenshots) see exactly what the ROM drew.
+use std::fmt;
+
+use crate::palette::Rgb;
+
+// How much of a pixel's brightness carries over to the next frame
+pub const DEFAULT_DECAY: f32 = 0.6;
+// Levels below this are too dim to see and count as off
+const CUTOFF: f32 = 1.0 / 256.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PersistenceMode {
+    // Show gfx as it is
+    #[default]
+    Off,
+    // Lit pixels fade out over a few frames like a CRT's phosphor
+    Blend,
+    // A pixel is lit if it was lit in this frame or the one before
+    Or,
+}
+
+impl PersistenceMode {
+    pub const NAMES: [&'static str; 3] = ["off", "blend", "or"];
+
+    pub fn from_name(name: &str) -> Option<PersistenceMode> {
+        match name.to_lowercase().as_str() {
+            "off" => Some(PersistenceMode::Off),
+            "blend" => Some(PersistenceMode::Blend),
+            "or" => Some(PersistenceMode::Or),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PersistenceMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PersistenceMode::Off => "off",
+            PersistenceMode::Blend => "blend",
+            PersistenceMode::Or => "or",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub struct Phosphor {
+    pub mode: PersistenceMode,
+    // Blend: fraction of the brightness left after each frame, 0 to 1
+    pub decay: f32,
+    width: usize,
+    height: usize,
+    // Blend: how lit each pixel still is, and the gfx value it was lit with
+    levels: Vec<f32>,
+    lit_with: Vec<u8>,
+    // Or: the last frame and the one before it
+    last: Vec<u8>,
+    previous: Vec<u8>,
+    out: Vec<Rgb>,
+}
+
+impl Phosphor {
+    pub fn new(mode: PersistenceMode, decay: f32) -> Self {
+        Self {
+            mode,
+            decay: decay.clamp(0.0, 1.0),
+            width: 0,
+            height: 0,
+            levels: Vec::new(),
+            lit_with: Vec::new(),
+            last: Vec::new(),
+            previous: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, gfx: &[u8], width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            // New resolution, start from a clean screen
+            let len = width * height;
+            (self.width, self.height) = (width, height);
+            self.levels = vec![0.0; len];
+            self.lit_with = vec![0; len];
+            self.last = gfx[..len].to_vec();
+            self.previous = gfx[..len].to_vec();
+        }
+    }
+
+    // Ages the picture by one emulated frame, gfx being the screen at its
+    // end. Call once per 60 Hz frame, not per redraw, so a paused machine
+    // keeps its picture. Returns true if that changed the picture even
+    // where gfx did not, so the frontend should show it again.
+    pub fn step(&mut self, gfx: &[u8], width: usize, height: usize) -> bool {
+        self.resize(gfx, width, height);
+        let len = width * height;
+        match self.mode {
+            PersistenceMode::Off => false,
+            PersistenceMode::Blend => {
+                let mut faded = false;
+                for (n, &px) in gfx[..len].iter().enumerate() {
+                    if px != 0 {
+                        self.levels[n] = 1.0;
+                        self.lit_with[n] = px & 0x3;
+                    } else if self.levels[n] > 0.0 {
+                        self.levels[n] *= self.decay;
+                        if self.levels[n] < CUTOFF {
+                            self.levels[n] = 0.0;
+                        }
+                        faded = true;
+                    }
+                }
+                faded
+            }
+            PersistenceMode::Or => {
+                // Drops what only the frame before last had lit
+                let faded = self.previous != self.last;
+                std::mem::swap(&mut self.previous, &mut self.last);
+                self.last.copy_from_slice(&gfx[..len]);
+                faded
+            }
+        }
+    }
+
+    // Colour of every pixel of gfx as it should be shown, row-major. Only
+    // draws what step left behind, so it can be called for every redraw.
+    pub fn apply(&mut self, gfx: &[u8], width: usize, height: usize, colors: &[Rgb; 4]) -> &[Rgb] {
+        self.resize(gfx, width, height);
+        let len = width * height;
+        self.out.clear();
+        match self.mode {
+            PersistenceMode::Off => {
+                self.out.extend(gfx[..len].iter().map(|&px| colors[(px & 0x3) as usize]));
+            }
+            PersistenceMode::Blend => {
+                let bg = colors[0];
+                for (n, &px) in gfx[..len].iter().enumerate() {
+                    // Lit pixels are at full brightness even before the
+                    // frame they were drawn in ends
+                    let (level, lit_with) = if px != 0 { (1.0, px & 0x3) } else { (self.levels[n], self.lit_with[n]) };
+                    // Part way from the background to the colour it was lit with
+                    let fg = colors[lit_with as usize];
+                    let mix = |f: u8, b: u8| (b as f32 + (f as f32 - b as f32) * level).round() as u8;
+                    self.out.push((mix(fg.0, bg.0), mix(fg.1, bg.1), mix(fg.2, bg.2)));
+                }
+            }
+            PersistenceMode::Or => {
+                self.out.extend(gfx[..len].iter().zip(self.previous.iter())
+                    .map(|(&now, &before)| colors[((now | before) & 0x3) as usize]));
+            }
+        }
+        &self.out
+    }
+}
+
+impl Default for Phosphor {
+    fn default() -> Self {
+        Self::new(PersistenceMode::default(), DEFAULT_DECAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [Rgb; 4] = [(0, 0, 0), (200, 100, 50), (10, 20, 30), (255, 255, 255)];
+    const BG: Rgb = COLORS[0];
+
+    #[test]
+    fn off_shows_gfx_as_it_is() {
+        let mut phosphor = Phosphor::new(PersistenceMode::Off, DEFAULT_DECAY);
+        let gfx = [0, 1, 2, 3];
+        assert!(!phosphor.step(&gfx, 2, 2));
+        assert_eq!(phosphor.apply(&gfx, 2, 2, &COLORS), COLORS);
+        assert!(!phosphor.step(&[0; 4], 2, 2));
+        assert_eq!(phosphor.apply(&[0; 4], 2, 2, &COLORS), [BG; 4]);
+    }
+
+    #[test]
+    fn blend_fades_by_decay_each_frame() {
+        let mut phosphor = Phosphor::new(PersistenceMode::Blend, 0.5);
+        phosphor.step(&[1, 3], 2, 1);
+        assert_eq!(phosphor.apply(&[1, 3], 2, 1, &COLORS), [COLORS[1], COLORS[3]]);
+        // Pixels fade towards the background in the colour they were lit with
+        assert!(phosphor.step(&[0, 0], 2, 1));
+        assert_eq!(phosphor.apply(&[0, 0], 2, 1, &COLORS), [(100, 50, 25), (128, 128, 128)]);
+        assert!(phosphor.step(&[0, 0], 2, 1));
+        assert_eq!(phosphor.apply(&[0, 0], 2, 1, &COLORS), [(50, 25, 13), (64, 64, 64)]);
+        // Relighting one goes straight back to full brightness
+        phosphor.step(&[2, 0], 2, 1);
+        assert_eq!(phosphor.apply(&[2, 0], 2, 1, &COLORS), [COLORS[2], (32, 32, 32)]);
+        // Until both are below the cutoff and count as off
+        let mut frames = 0;
+        while phosphor.step(&[0, 0], 2, 1) {
+            frames += 1;
+        }
+        assert_eq!(frames, 9);
+        assert_eq!(phosphor.apply(&[0, 0], 2, 1, &COLORS), [BG; 2]);
+    }
+
+    #[test]
+    fn blend_with_no_decay_is_off() {
+        let mut phosphor = Phosphor::new(PersistenceMode::Blend, -1.0);
+        phosphor.step(&[1, 1], 2, 1);
+        assert!(phosphor.step(&[0, 1], 2, 1));
+        assert_eq!(phosphor.apply(&[0, 1], 2, 1, &COLORS), [BG, COLORS[1]]);
+    }
+
+    #[test]
+    fn or_keeps_one_frame() {
+        let mut phosphor = Phosphor::new(PersistenceMode::Or, DEFAULT_DECAY);
+        assert!(!phosphor.step(&[1, 0, 0], 3, 1));
+        // A sprite moving right by XOR erasing and redrawing stays lit
+        assert!(!phosphor.step(&[0, 1, 0], 3, 1));
+        assert_eq!(phosphor.apply(&[0, 1, 0], 3, 1, &COLORS), [COLORS[1], COLORS[1], BG]);
+        // Planes are ORed too
+        assert!(phosphor.step(&[0, 2, 0], 3, 1));
+        assert_eq!(phosphor.apply(&[0, 2, 0], 3, 1, &COLORS), [BG, COLORS[3], BG]);
+        assert!(phosphor.step(&[0; 3], 3, 1));
+        assert_eq!(phosphor.apply(&[0; 3], 3, 1, &COLORS), [BG, COLORS[2], BG]);
+        assert!(phosphor.step(&[0; 3], 3, 1));
+        assert_eq!(phosphor.apply(&[0; 3], 3, 1, &COLORS), [BG; 3]);
+        assert!(!phosphor.step(&[0; 3], 3, 1));
+    }
+
+    #[test]
+    fn paused_frames_leave_the_picture_alone() {
+        for mode in [PersistenceMode::Blend, PersistenceMode::Or] {
+            let mut phosphor = Phosphor::new(mode, DEFAULT_DECAY);
+            phosphor.step(&[1, 2, 3, 0], 2, 2);
+            phosphor.step(&[0, 0, 1, 0], 2, 2);
+            let shown = phosphor.apply(&[0, 0, 1, 0], 2, 2, &COLORS).to_vec();
+            assert_ne!(shown, [BG, BG, COLORS[1], BG], "{} should show a trail", mode);
+            // Redraws without an emulated frame in between
+            for _ in 0..10 {
+                assert_eq!(phosphor.apply(&[0, 0, 1, 0], 2, 2, &COLORS), shown);
+            }
+        }
+    }
+
+    #[test]
+    fn new_resolution_starts_clean() {
+        let mut phosphor = Phosphor::new(PersistenceMode::Blend, DEFAULT_DECAY);
+        phosphor.step(&[1, 1], 2, 1);
+        phosphor.step(&[0, 0], 2, 1);
+        assert!(!phosphor.step(&[0; 4], 4, 1));
+        assert_eq!(phosphor.apply(&[0; 4], 4, 1, &COLORS), [BG; 4]);
+    }
+}
@@ -0,0 +1,177 @@
+// CHIP-8 was never formally specified, and the interpreters that followed the
+// original COSMAC VIP one disagree on a handful of instructions. ROMs were
+// written against whichever interpreter their author had, so we let the user
+// pick the behaviour instead of hard-coding one mix of it.
+
+// Where Fx55/Fx65 leave I
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreI {
+    // Where it was (SUPER-CHIP)
+    Keep,
+    // Past the last register transferred, I += x + 1 (VIP)
+    AddXPlusOne,
+    // On the last register transferred, I += x (CHIP-48)
+    AddX,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx (VIP) instead of shifting Vx in place
+    pub shift_vy: bool,
+    pub load_store_i: LoadStoreI,
+    // Bnnn is really Bxnn and jumps to xnn + Vx (CHIP-48/SUPER-CHIP)
+    pub jump_vx: bool,
+    // 8xy1/8xy2/8xy3 clear VF as a side effect (VIP)
+    pub vf_reset: bool,
+    // Dxyn clips sprites at the screen edges instead of wrapping them around
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store_i: LoadStoreI::AddXPlusOne,
+        jump_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+    };
+    pub const CHIP_48: Quirks = Quirks {
+        shift_vy: false,
+        load_store_i: LoadStoreI::AddX,
+        jump_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+    };
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_vy: false,
+        load_store_i: LoadStoreI::Keep,
+        jump_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+    };
+    // Octo's XO-CHIP behaviour
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_vy: false,
+        load_store_i: LoadStoreI::AddXPlusOne,
+        jump_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+    };
+    // What most present-day interpreters (and most ROM test suites) assume
+    pub const MODERN: Quirks = Quirks {
+        shift_vy: false,
+        load_store_i: LoadStoreI::Keep,
+        jump_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+    };
+
+    // Names accepted on the command line
+    pub const PRESET_NAMES: [&'static str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+    // Looks up a preset by its command line name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{Chip8, DISP_X};
+
+    // Runs a program up to the first 0000 word, which can be followed by data
+    fn run(quirks: Quirks, program: &[u16], setup: impl Fn(&mut Chip8)) -> Chip8 {
+        let mut c8 = Chip8::with_quirks(quirks);
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        c8.load_rom_bytes(&rom).unwrap();
+        setup(&mut c8);
+        for _ in program.iter().take_while(|&&word| word != 0) {
+            c8.cycle().unwrap();
+        }
+        c8
+    }
+
+    #[test]
+    fn shift_vy() {
+        let setup = |c8: &mut Chip8| {
+            c8.set_v(0, 0x01);
+            c8.set_v(1, 0x80);
+        };
+        // SHR V0, V1
+        let c8 = run(Quirks { shift_vy: true, ..Quirks::MODERN }, &[0x8016], setup);
+        assert_eq!((c8.v()[0], c8.v()[0xF]), (0x40, 0));
+        let c8 = run(Quirks::MODERN, &[0x8016], setup);
+        assert_eq!((c8.v()[0], c8.v()[0xF]), (0x00, 1));
+        // SHL V0, V1
+        let c8 = run(Quirks { shift_vy: true, ..Quirks::MODERN }, &[0x801E], setup);
+        assert_eq!((c8.v()[0], c8.v()[0xF]), (0x00, 1));
+        let c8 = run(Quirks::MODERN, &[0x801E], setup);
+        assert_eq!((c8.v()[0], c8.v()[0xF]), (0x02, 0));
+    }
+
+    #[test]
+    fn load_store_i() {
+        // LD I, 0x300, LD [I], V2, LD V2, [I]
+        for (mode, after) in [(LoadStoreI::Keep, 0x300), (LoadStoreI::AddXPlusOne, 0x306), (LoadStoreI::AddX, 0x304)] {
+            let c8 = run(Quirks { load_store_i: mode, ..Quirks::MODERN }, &[0xA300, 0xF255, 0xF265], |_| {});
+            assert_eq!(c8.i(), after, "{:?}", mode);
+        }
+        assert_eq!(Quirks::COSMAC_VIP.load_store_i, LoadStoreI::AddXPlusOne);
+        assert_eq!(Quirks::CHIP_48.load_store_i, LoadStoreI::AddX);
+        assert_eq!(Quirks::SUPER_CHIP.load_store_i, LoadStoreI::Keep);
+    }
+
+    #[test]
+    fn jump_vx() {
+        let setup = |c8: &mut Chip8| {
+            c8.set_v(0, 0x10);
+            c8.set_v(2, 0x20);
+        };
+        // JP V0, 0x234
+        assert_eq!(run(Quirks::MODERN, &[0xB234], setup).pc(), 0x244);
+        assert_eq!(run(Quirks { jump_vx: true, ..Quirks::MODERN }, &[0xB234], setup).pc(), 0x254);
+    }
+
+    #[test]
+    fn vf_reset() {
+        // OR, AND and XOR V0, V1
+        for opcode in [0x8011, 0x8012, 0x8013] {
+            let setup = |c8: &mut Chip8| c8.set_v(0xF, 5);
+            assert_eq!(run(Quirks::MODERN, &[opcode], setup).v()[0xF], 5);
+            assert_eq!(run(Quirks { vf_reset: true, ..Quirks::MODERN }, &[opcode], setup).v()[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn clip_sprites() {
+        // A 2 row sprite of 8 pixels each at (62, 31)
+        let program = [0xA206, 0xD012, 0x0000, 0xFFFF];
+        let setup = |c8: &mut Chip8| {
+            c8.set_v(0, 62);
+            c8.set_v(1, 31);
+        };
+        let lit = |c8: &Chip8| -> Vec<(usize, usize)> {
+            c8.screen().iter().enumerate().filter(|&(_, &px)| px != 0).map(|(n, _)| (n % DISP_X, n / DISP_X)).collect()
+        };
+        let c8 = run(Quirks { clip_sprites: true, ..Quirks::MODERN }, &program, setup);
+        assert_eq!(lit(&c8), [(62, 31), (63, 31)]);
+        let c8 = run(Quirks::MODERN, &program, setup);
+        let pixels = lit(&c8);
+        assert_eq!(pixels.len(), 16);
+        assert!(pixels.contains(&(5, 0)) && pixels.contains(&(62, 0)) && pixels.contains(&(5, 31)));
+    }
+}
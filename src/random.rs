@@ -0,0 +1,214 @@
+// Random numbers for Cxkk. The machine owns its generator so runs can be
+// reproduced from a seed, and anything implementing RandomSource can be
+// plugged in (e.g. a fixed sequence in a test harness).
+use std::fmt;
+
+pub trait RandomSource: fmt::Debug + Send {
+    // Next byte for Cxkk, before it is masked with kk
+    fn next_byte(&mut self, mem: &[u8]) -> u8;
+    // Called once per 60 Hz frame
+    fn tick(&mut self) {}
+    // Restarts the sequence. seed(state()) puts the generator back exactly
+    // where it was, which is how save states restore it.
+    fn seed(&mut self, seed: u64);
+    fn state(&self) -> u64;
+    // Which built-in generator this is, so save states can bring it back.
+    // None for anything else.
+    fn mode(&self) -> Option<RngMode> {
+        None
+    }
+}
+
+// SplitMix64. Every byte value is equally likely, including 0xFF.
+#[derive(Debug, Clone)]
+pub struct Uniform {
+    state: u64,
+}
+
+impl Uniform {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RandomSource for Uniform {
+    fn next_byte(&mut self, _mem: &[u8]) -> u8 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        (z ^ (z >> 31)) as u8
+    }
+    fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+    fn state(&self) -> u64 {
+        self.state
+    }
+    fn mode(&self) -> Option<RngMode> {
+        Some(RngMode::Uniform)
+    }
+}
+
+// Works like the COSMAC VIP interpreter: a pointer that the 60 Hz interrupt
+// also advances picks a byte out of the low page of memory, which is added to
+// a running total. Results depend on when Cxkk runs, not just how often.
+// The VIP summed bytes of its own interpreter code; ours isn't in memory,
+// so the fonts living there stand in for it.
+#[derive(Debug, Clone)]
+pub struct Vip {
+    ptr: u8,
+    total: u8,
+}
+
+impl Vip {
+    pub fn new(seed: u64) -> Self {
+        let mut vip = Self { ptr: 0, total: 0 };
+        vip.seed(seed);
+        vip
+    }
+}
+
+impl RandomSource for Vip {
+    fn next_byte(&mut self, mem: &[u8]) -> u8 {
+        self.ptr = self.ptr.wrapping_add(1);
+        self.total = self.total.wrapping_add(mem[self.ptr as usize]);
+        self.total
+    }
+    fn tick(&mut self) {
+        self.ptr = self.ptr.wrapping_add(1);
+    }
+    fn seed(&mut self, seed: u64) {
+        self.ptr = seed as u8;
+        self.total = (seed >> 8) as u8;
+    }
+    fn state(&self) -> u64 {
+        ((self.total as u64) << 8) | self.ptr as u64
+    }
+    fn mode(&self) -> Option<RngMode> {
+        Some(RngMode::Vip)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngMode {
+    #[default]
+    Uniform,
+    Vip,
+}
+
+impl RngMode {
+    pub const NAMES: [&'static str; 2] = ["uniform", "vip"];
+
+    pub fn from_name(name: &str) -> Option<RngMode> {
+        match name.to_lowercase().as_str() {
+            "uniform" => Some(RngMode::Uniform),
+            "vip" => Some(RngMode::Vip),
+            _ => None,
+        }
+    }
+
+    pub fn source(self, seed: u64) -> Box<dyn RandomSource> {
+        match self {
+            RngMode::Uniform => Box::new(Uniform::new(seed)),
+            RngMode::Vip => Box::new(Vip::new(seed)),
+        }
+    }
+}
+
+impl fmt::Display for RngMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RngMode::Uniform => "uniform",
+            RngMode::Vip => "vip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    fn bytes(rng: &mut dyn RandomSource, mem: &[u8], n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_byte(mem)).collect()
+    }
+
+    #[test]
+    fn uniform_sequence_is_fixed() {
+        // Low byte of SplitMix64's well known first output 0xE220A8397B1DCDAF
+        assert_eq!(Uniform::new(0).next_byte(&[]), 0xAF);
+        assert_eq!(bytes(&mut Uniform::new(1234), &[], 8), [0xDB, 0x24, 0xDA, 0x33, 0xA5, 0x11, 0x9E, 0x9F]);
+        assert_ne!(bytes(&mut Uniform::new(1235), &[], 8), bytes(&mut Uniform::new(1234), &[], 8));
+    }
+
+    #[test]
+    fn uniform_covers_every_byte() {
+        let mut rng = Uniform::new(1);
+        let mut seen = [false; 256];
+        for _ in 0..10_000 {
+            seen[rng.next_byte(&[]) as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn vip_sequence_is_fixed() {
+        let mem: Vec<u8> = (0..=255u8).map(|n| n.wrapping_mul(3)).collect();
+        // Seed is total << 8 | pointer
+        let mut rng = Vip::new(0x0502);
+        assert_eq!(bytes(&mut rng, &mem, 2), [5 + 9, 5 + 9 + 12]);
+        // A frame going by skips a byte
+        rng.tick();
+        assert_eq!(rng.next_byte(&mem), 5 + 9 + 12 + 18);
+        assert_eq!(rng.state(), (44 << 8) | 6);
+        // The pointer wraps around the page
+        let mut rng = Vip::new(0xFF);
+        assert_eq!(rng.next_byte(&mem), 0);
+    }
+
+    #[test]
+    fn state_reseeds_to_the_same_place() {
+        let mem: Vec<u8> = (0..=255u8).map(|n| n.wrapping_mul(7)).collect();
+        for mode in [RngMode::Uniform, RngMode::Vip] {
+            let mut rng = mode.source(99);
+            bytes(&mut *rng, &mem, 37);
+            rng.tick();
+            let mut copy = mode.source(0);
+            copy.seed(rng.state());
+            assert_eq!(copy.mode(), Some(mode));
+            assert_eq!(bytes(&mut *copy, &mem, 50), bytes(&mut *rng, &mem, 50));
+        }
+    }
+
+    #[test]
+    fn seed_survives_a_save_state() {
+        // V0-VF = random, forever
+        let rom: Vec<u8> = (0..16u16).map(|x| 0xC0FF | (x << 8)).chain([0x1200]).flat_map(|w| w.to_be_bytes()).collect();
+        let run = |c8: &mut Chip8| {
+            for _ in 0..17 {
+                c8.cycle().unwrap();
+            }
+            c8.v().to_vec()
+        };
+        let machine = |rng: Box<dyn RandomSource>| {
+            let mut c8 = Chip8::new();
+            c8.load_font();
+            c8.load_rom_bytes(&rom).unwrap();
+            c8.rng = rng;
+            c8
+        };
+        for mode in [RngMode::Uniform, RngMode::Vip] {
+            let mut original = machine(mode.source(42));
+            run(&mut original);
+            original.end_frame(&mut crate::frontend::AudioCapture::default());
+            let state = original.save_state();
+
+            let mut restored = machine(RngMode::Uniform.source(7));
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.rng.state(), original.rng.state());
+            assert_eq!(run(&mut restored), run(&mut original));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// Rewind buffer. After every frame the machine's save state is pushed here.
+// Only the newest state is kept whole; for each older frame we keep a delta
+// that turns the state after it back into it. Consecutive frames differ in a
+// handful of bytes, so a delta is usually a few dozen bytes instead of a
+// full 12 KiB (or 64 KiB with XO-CHIP) snapshot.
+//
+// Delta format: new length, then (zero run, literal length, literal bytes)
+// groups covering the XOR of the two states, all lengths as LEB128 varints.
+use std::collections::VecDeque;
+
+use crate::scheduler::FRAME_RATE;
+
+pub const DEFAULT_SECONDS: u32 = 10;
+pub const DEFAULT_BUDGET: usize = 16 * 1024 * 1024;
+
+pub struct Rewind {
+    // Newest state, whole
+    current: Vec<u8>,
+    // Oldest first. The last delta turns `current` into the frame before it.
+    deltas: VecDeque<Vec<u8>>,
+    // Bytes held by current and deltas
+    used: usize,
+    // Most frames that can be stepped back
+    pub max_depth: usize,
+    // Most bytes to hold before the oldest frames are dropped
+    pub budget: usize,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = data.get(*pos) {
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+// Delta that turns `from` into `to`
+pub fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_varint(&mut out, to.len());
+    let diff = |n: usize| to[n] ^ from.get(n).copied().unwrap_or(0);
+    let mut n = 0;
+    while n < to.len() {
+        let start = n;
+        while n < to.len() && diff(n) == 0 {
+            n += 1;
+        }
+        let zeros = n - start;
+        let literal_start = n;
+        // Short zero runs inside a literal are cheaper kept than split off
+        while n < to.len() && (diff(n) != 0 || (n + 1 < to.len() && diff(n + 1) != 0)) {
+            n += 1;
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, n - literal_start);
+        out.extend((literal_start..n).map(diff));
+    }
+    out
+}
+
+// Reverses encode_delta: apply_delta(from, &encode_delta(from, to)) == to
+pub fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut out: Vec<u8> = (0..len).map(|n| from.get(n).copied().unwrap_or(0)).collect();
+    let mut n = 0;
+    while n < len && pos < delta.len() {
+        n += read_varint(delta, &mut pos);
+        let literal = read_varint(delta, &mut pos);
+        for _ in 0..literal {
+            out[n] ^= delta[pos];
+            n += 1;
+            pos += 1;
+        }
+    }
+    out
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_SECONDS, DEFAULT_BUDGET)
+    }
+}
+
+impl Rewind {
+    // Keeps up to `seconds` of frames in at most `budget` bytes
+    pub fn new(seconds: u32, budget: usize) -> Self {
+        Self {
+            current: Vec::new(),
+            deltas: VecDeque::new(),
+            used: 0,
+            max_depth: (seconds * FRAME_RATE) as usize,
+            budget,
+        }
+    }
+
+    // Frames that can currently be stepped back
+    pub fn depth(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    // Records the state at the end of a frame
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.max_depth == 0 {
+            return;
+        }
+        if !self.current.is_empty() {
+            let delta = encode_delta(&state, &self.current);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.used = self.used - self.current.len() + state.len();
+        self.current = state;
+        while self.deltas.len() > self.max_depth || (self.used > self.budget && !self.deltas.is_empty()) {
+            if let Some(oldest) = self.deltas.pop_front() {
+                self.used -= oldest.len();
+            }
+        }
+    }
+
+    // Steps one frame back and returns that frame's state to load, or None
+    // once the oldest frame kept has been reached
+    pub fn step_back(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        let previous = apply_delta(&self.current, &delta);
+        self.used = self.used - delta.len() - self.current.len() + previous.len();
+        self.current = previous;
+        Some(&self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // States that change a little from one frame to the next, like a
+    // machine's do, and now and then change size
+    fn states(count: usize) -> Vec<Vec<u8>> {
+        let mut seed: u32 = 0x2545F491;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+        let mut state = vec![0u8; 4096];
+        let mut all = Vec::new();
+        for frame in 0..count {
+            for _ in 0..random() % 40 {
+                let n = random() % state.len();
+                state[n] = random() as u8;
+            }
+            if frame % 17 == 16 {
+                state.resize(if state.len() == 4096 { 8192 } else { 4096 }, 0xAA);
+            }
+            all.push(state.clone());
+        }
+        all
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let a: Vec<u8> = (0..=255).collect();
+        let mut b = a.clone();
+        b[3] ^= 1;
+        b[4] ^= 1;
+        b[200] = 0;
+        let cases: [(&[u8], &[u8]); 6] = [
+            (&a, &a), (&a, &b), (&b, &a), (&a, &a[..10]), (&a[..10], &a), (&[], &b),
+        ];
+        for (from, to) in cases {
+            assert_eq!(apply_delta(from, &encode_delta(from, to)), to);
+        }
+        // Nothing changed costs the length, one zero run and an empty literal
+        assert_eq!(encode_delta(&a, &a), [0x80, 0x02, 0x80, 0x02, 0x00]);
+        let inverted: Vec<u8> = a.iter().map(|b| !b).collect();
+        assert_eq!(apply_delta(&a, &encode_delta(&a, &inverted)), inverted);
+    }
+
+    #[test]
+    fn steps_back_through_every_frame() {
+        let states = states(200);
+        let mut rewind = Rewind::new(10, usize::MAX);
+        for state in states.iter() {
+            rewind.push(state.clone());
+        }
+        assert_eq!(rewind.depth(), 199);
+        for expected in states.iter().rev().skip(1) {
+            assert_eq!(rewind.step_back(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.step_back(), None);
+        assert_eq!(rewind.memory_used(), states[0].len());
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_past_the_limits() {
+        let states = states(300);
+
+        // One second of frames
+        let mut rewind = Rewind::new(1, usize::MAX);
+        for state in states.iter() {
+            rewind.push(state.clone());
+        }
+        assert_eq!(rewind.depth(), FRAME_RATE as usize);
+        for expected in states.iter().rev().skip(1).take(FRAME_RATE as usize) {
+            assert_eq!(rewind.step_back(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.step_back(), None);
+
+        // Out of memory long before that
+        let budget = 20_000;
+        let mut rewind = Rewind::new(10, budget);
+        for state in states.iter() {
+            rewind.push(state.clone());
+            assert!(rewind.memory_used() <= budget);
+        }
+        let depth = rewind.depth();
+        assert!(depth > 0 && depth < 299);
+        for expected in states.iter().rev().skip(1).take(depth) {
+            assert_eq!(rewind.step_back(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.step_back(), None);
+    }
+
+    #[test]
+    fn zero_seconds_keeps_nothing() {
+        let mut rewind = Rewind::new(0, usize::MAX);
+        for state in states(5) {
+            rewind.push(state);
+        }
+        assert_eq!(rewind.memory_used(), 0);
+        assert_eq!(rewind.step_back(), None);
+    }
+}
@@ -0,0 +1,286 @@
+// Save states: a snapshot of the whole machine as a little-endian binary
+// blob. Layout (version 3):
+//   magic "C8ST", version u16, ROM hash u64, xo_chip u8,
+//   pc u16, i u16, opcode u16, dt u8, st u8, plane u8, resolution u8,
+//   key_wait u8 (0xFF = none), exit_flag u8, RNG state u64,
+//   RNG mode u8 (0 = uniform, 1 = vip, 0xFF = some other source),
+//   v [16], rpl [16], keys [16],
+//   stack depth u8 + u16 entries, mem length u32 + bytes, gfx length u32 + bytes
+// Version 1 had neither RNG field and version 2 had no RNG mode; they still
+// load, leaving the generator (or its mode) as it is.
+// Chip8::save_state and Chip8::load_state do the actual (de)serialization.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::Chip8;
+
+pub const MAGIC: [u8; 4] = *b"C8ST";
+// Bump whenever the layout changes, and teach Chip8::load_state the old one
+pub const VERSION: u16 = 3;
+// Oldest version that can still be loaded
+pub const MIN_VERSION: u16 = 1;
+pub const SAVE_DIR: &str = "./saves";
+
+#[derive(Debug)]
+pub enum StateError {
+    // Not a save state at all
+    BadMagic,
+    UnsupportedVersion(u16),
+    // The state was saved while running a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    // Header was fine but a field holds an impossible value
+    Corrupt(&'static str),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic =>
+                write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) =>
+                write!(f, "save state version {} is not supported (expected {} to {})", v, MIN_VERSION, VERSION),
+            StateError::RomMismatch { expected, found } =>
+                write!(f, "save state is for a different ROM (hash {:016X}, running {:016X})", found, expected),
+            StateError::Truncated =>
+                write!(f, "save state is truncated"),
+            StateError::Corrupt(what) =>
+                write!(f, "save state is corrupt: bad {}", what),
+            StateError::Io(e) =>
+                write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StateError {
+    fn from(e: std::io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+// 64-bit FNV-1a, enough to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    pub buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(StateError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+// saves/<rom file name>.<slot>.state
+pub fn slot_path(rom_path: &str, slot: u8) -> PathBuf {
+    let name = Path::new(rom_path).file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "rom".to_string());
+    Path::new(SAVE_DIR).join(format!("{}.{}.state", name, slot))
+}
+
+pub fn save_slot(c8: &Chip8, rom_path: &str, slot: u8) -> Result<PathBuf, StateError> {
+    let path = slot_path(rom_path, slot);
+    std::fs::create_dir_all(SAVE_DIR)?;
+    std::fs::write(&path, c8.save_state())?;
+    Ok(path)
+}
+
+pub fn load_slot(c8: &mut Chip8, rom_path: &str, slot: u8) -> Result<PathBuf, StateError> {
+    let path = slot_path(rom_path, slot);
+    let data = std::fs::read(&path)?;
+    c8.load_state(&data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::RngMode;
+
+    // Draws random sprites forever
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xC1, 0x1F, 0xD0, 0x15, 0x72, 0x01, 0x12, 0x00];
+    // Offsets of header fields, see the layout at the top
+    const VERSION_AT: usize = 4;
+    const RESOLUTION_AT: usize = 24;
+    const RNG_STATE_AT: usize = 27;
+    const RNG_MODE_AT: usize = 35;
+
+    fn machine(rng: RngMode) -> Chip8 {
+        let mut c8 = Chip8::new();
+        c8.load_font();
+        c8.load_rom_bytes(&ROM).unwrap();
+        c8.rng = rng.source(1234);
+        c8
+    }
+
+    fn running(rng: RngMode, cycles: usize) -> Chip8 {
+        let mut c8 = machine(rng);
+        for _ in 0..cycles {
+            c8.cycle().unwrap();
+        }
+        c8
+    }
+
+    #[test]
+    fn round_trip_restores_everything() {
+        for mode in [RngMode::Uniform, RngMode::Vip] {
+            let mut original = running(mode, 101);
+            let state = original.save_state();
+            assert_eq!(&state[..4], MAGIC);
+            assert_eq!(u16::from_le_bytes([state[VERSION_AT], state[VERSION_AT + 1]]), VERSION);
+
+            let mut restored = machine(mode);
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.save_state(), state);
+            // And it carries on exactly the same way
+            for _ in 0..100 {
+                original.cycle().unwrap();
+                restored.cycle().unwrap();
+            }
+            assert_eq!(restored.save_state(), original.save_state());
+        }
+    }
+
+    #[test]
+    fn rng_mode_is_restored() {
+        let saved = running(RngMode::Vip, 50);
+        let mut c8 = machine(RngMode::Uniform);
+        c8.load_state(&saved.save_state()).unwrap();
+        assert_eq!(c8.rng.mode(), Some(RngMode::Vip));
+        assert_eq!(c8.rng.state(), saved.rng.state());
+    }
+
+    #[test]
+    fn older_versions_still_load() {
+        let saved = running(RngMode::Vip, 50);
+        let state = saved.save_state();
+
+        // Version 2: no RNG mode, the machine keeps its own
+        let mut v2 = state.clone();
+        v2.remove(RNG_MODE_AT);
+        v2[VERSION_AT..VERSION_AT + 2].copy_from_slice(&2u16.to_le_bytes());
+        let mut c8 = machine(RngMode::Uniform);
+        c8.load_state(&v2).unwrap();
+        assert_eq!(c8.rng.mode(), Some(RngMode::Uniform));
+        assert_eq!(c8.rng.state(), saved.rng.state());
+        assert_eq!(c8.pc(), saved.pc());
+
+        // Version 1: no RNG at all, the generator is left alone
+        let mut v1 = state.clone();
+        v1.drain(RNG_STATE_AT..RNG_MODE_AT + 1);
+        v1[VERSION_AT..VERSION_AT + 2].copy_from_slice(&1u16.to_le_bytes());
+        let mut c8 = machine(RngMode::Uniform);
+        let before = c8.rng.state();
+        c8.load_state(&v1).unwrap();
+        assert_eq!(c8.rng.state(), before);
+        assert_eq!(c8.v(), saved.v());
+        assert_eq!(c8.screen(), saved.screen());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let state = running(RngMode::Uniform, 10).save_state();
+        let mut c8 = machine(RngMode::Uniform);
+
+        let mut bad = state.clone();
+        bad[0] = b'X';
+        assert!(matches!(c8.load_state(&bad), Err(StateError::BadMagic)));
+
+        for version in [0, VERSION + 1] {
+            let mut bad = state.clone();
+            bad[VERSION_AT..VERSION_AT + 2].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(c8.load_state(&bad), Err(StateError::UnsupportedVersion(v)) if v == version));
+        }
+
+        let mut other = Chip8::new();
+        other.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        assert!(matches!(other.load_state(&state), Err(StateError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn truncated_states_change_nothing() {
+        let state = running(RngMode::Uniform, 10).save_state();
+        let mut c8 = running(RngMode::Uniform, 3);
+        let before = c8.save_state();
+        for len in 0..state.len() {
+            assert!(matches!(c8.load_state(&state[..len]), Err(StateError::Truncated)), "length {}", len);
+        }
+        assert_eq!(c8.save_state(), before);
+    }
+
+    #[test]
+    fn impossible_values_are_corrupt() {
+        let state = running(RngMode::Uniform, 10).save_state();
+        let mut c8 = machine(RngMode::Uniform);
+        let before = c8.save_state();
+        for (at, value, what) in [(RESOLUTION_AT, 7, "resolution"), (RESOLUTION_AT + 1, 0x20, "key wait"),
+                                  (RNG_MODE_AT, 9, "RNG mode"), (RNG_MODE_AT + 49, 17, "stack depth")] {
+            let mut bad = state.clone();
+            bad[at] = value;
+            match c8.load_state(&bad) {
+                Err(StateError::Corrupt(field)) => assert_eq!(field, what),
+                other => panic!("{} gave {:?}", what, other),
+            }
+        }
+        assert_eq!(c8.save_state(), before);
+    }
+}
@@ -0,0 +1,66 @@
+// Paces emulation against the wall clock. The machine advances in whole
+// 60 Hz frames (see Chip8::run_frame); the scheduler decides how many frames
+// are due with a fixed-timestep accumulator, so a slow frame is caught up on
+// instead of slowing the game down.
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+// Instructions per frame. 11 * 60 is roughly the ~660 Hz most games expect.
+pub const DEFAULT_IPF: u32 = 11;
+// If we fall further behind than this (e.g. the window was being dragged),
+// drop the backlog instead of fast-forwarding through it
+const MAX_CATCH_UP: u32 = 4;
+
+pub struct Scheduler {
+    frame: Duration,
+    accumulator: Duration,
+    last: Instant,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            frame: Duration::from_secs(1) / FRAME_RATE,
+            accumulator: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    // Returns how many frames should be emulated to catch up with real time
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now - self.last;
+        self.last = now;
+
+        let mut due = 0;
+        while self.accumulator >= self.frame {
+            self.accumulator -= self.frame;
+            due += 1;
+        }
+        if due > MAX_CATCH_UP {
+            due = MAX_CATCH_UP;
+            self.accumulator = Duration::ZERO;
+        }
+        due
+    }
+
+    // Sleeps until the next frame is due
+    pub fn wait(&self) {
+        let until_next = self.frame.saturating_sub(self.accumulator + self.last.elapsed());
+        if !until_next.is_zero() {
+            std::thread::sleep(until_next);
+        }
+    }
+
+    // Forgets any time that has passed, e.g. after the emulator was paused
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.last = Instant::now();
+    }
+}
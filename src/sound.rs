@@ -0,0 +1,82 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use crate::frontend::AudioSink;
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+const SAMPLE_RATE: i32 = 44100;
+
+// Generates the square wave on SDL's audio thread
+pub struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase <= 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+// SDL square wave buzzer. If no audio device can be opened the emulator
+// keeps running silently.
+pub struct Beeper {
+    device: Option<AudioDevice<SquareWave>>,
+    pub muted: bool,
+    playing: bool,
+}
+
+impl Beeper {
+    // `frequency` is in Hz, `volume` goes from 0.0 to 1.0
+    pub fn new(sdl_context: &sdl2::Sdl, frequency: f32, volume: f32) -> Self {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = sdl_context.audio()
+            .and_then(|audio| audio.open_playback(None, &desired_spec, |spec| SquareWave {
+                phase_inc: frequency / spec.freq as f32,
+                phase: 0.0,
+                volume: volume.clamp(0.0, 1.0),
+            }));
+        let device = match device {
+            Ok(device) => Some(device),
+            Err(e) => {
+                eprintln!("Could not open audio device, sound is disabled: {}", e);
+                None
+            }
+        };
+        Self { device, muted: false, playing: false }
+    }
+
+    pub fn toggle_mute(&mut self){
+        self.muted = !self.muted;
+        println!("Sound {}", if self.muted { "muted" } else { "unmuted" });
+        if self.muted {
+            self.set_beeping(false);
+        }
+    }
+}
+
+impl AudioSink for Beeper {
+    fn set_beeping(&mut self, on: bool) {
+        let on = on && !self.muted;
+        if on == self.playing {
+            return;
+        }
+        if let Some(device) = &self.device {
+            if on {
+                device.resume();
+            } else {
+                device.pause();
+            }
+        }
+        self.playing = on;
+    }
+}
@@ -0,0 +1,260 @@
+// Terminal implementations of the frontend traits, for playing in a TTY
+// (over SSH too) without SDL. The screen is drawn with Unicode half blocks
+// or braille and only rows that changed are rewritten, in place.
+//
+// Terminals normally only report key presses, not releases. Where the
+// terminal supports the kitty keyboard protocol we ask for releases; other
+// terminals get a key held for KEY_HOLD after every press or auto-repeat.
+use std::fmt;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+                       PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::chip8::NUM_KEYS;
+use crate::frontend::{AudioSink, FrameSink, InputProvider};
+use crate::palette::{Palette, Rgb};
+
+// How long a key stays down after a press when releases aren't reported
+const KEY_HOLD: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    // One character per 1x2 pixels, in colour
+    #[default]
+    HalfBlock,
+    // One character per 2x4 pixels, lit or unlit only, in the foreground
+    // colour. Fits 128x64 into 64x16 characters.
+    Braille,
+}
+
+impl RenderMode {
+    pub const NAMES: [&'static str; 2] = ["halfblock", "braille"];
+
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name.to_lowercase().as_str() {
+            "halfblock" | "half-block" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RenderMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RenderMode::HalfBlock => "halfblock",
+            RenderMode::Braille => "braille",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn color((r, g, b): Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+// Renders gfx as lines of text, escape codes included
+fn render(gfx: &[u8], width: usize, height: usize, mode: RenderMode, colors: &[Rgb; 4]) -> Vec<String> {
+    let px = |x: usize, y: usize| if x < width && y < height { gfx[y * width + x] } else { 0 };
+    let mut lines = Vec::new();
+    match mode {
+        RenderMode::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                let mut line = String::new();
+                let mut current = None;
+                for x in 0..width {
+                    // Top pixel in the foreground, bottom in the background
+                    let cell = (px(x, y) & 0x3, px(x, y + 1) & 0x3);
+                    if current != Some(cell) {
+                        line += &format!("{}{}", SetForegroundColor(color(colors[cell.0 as usize])),
+                                         SetBackgroundColor(color(colors[cell.1 as usize])));
+                        current = Some(cell);
+                    }
+                    line.push('▀');
+                }
+                line += &ResetColor.to_string();
+                lines.push(line);
+            }
+        }
+        RenderMode::Braille => {
+            // Dot numbering of the U+2800 block, by (x, y) within the cell
+            const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+            let style = format!("{}{}", SetForegroundColor(color(colors[1])), SetBackgroundColor(color(colors[0])));
+            for y in (0..height).step_by(4) {
+                let dots: String = (0..width).step_by(2).map(|x| {
+                    let mut bits = 0;
+                    for (dx, column) in DOTS.iter().enumerate() {
+                        for (dy, &dot) in column.iter().enumerate() {
+                            if px(x + dx, y + dy) != 0 {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    char::from_u32(0x2800 + bits).unwrap_or(' ')
+                }).collect();
+                lines.push(format!("{}{}{}", style, dots, ResetColor));
+            }
+        }
+    }
+    lines
+}
+
+// Keypad layout matching the SDL frontend:
+//   1 2 3 4       1 2 3 C
+//   Q W E R  ->   4 5 6 D
+//   A S D F       7 8 9 E
+//   Z X C V       A 0 B F
+fn keypad(c: char) -> Option<usize> {
+    let layout = ['x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v'];
+    layout.iter().position(|&k| k == c.to_ascii_lowercase())
+}
+
+// Puts the terminal in raw mode on an alternate screen for as long as it
+// lives, and restores it when dropped (also on panic)
+pub struct Terminal {
+    out: Stdout,
+    pub mode: RenderMode,
+    pub palette: Palette,
+    // Lines currently on screen, to only rewrite what changed
+    shown: Vec<String>,
+    // The terminal reports key releases
+    releases: bool,
+    // When each key goes up, without releases
+    held_until: [Option<Instant>; NUM_KEYS],
+    // P was pressed since the last take_pause()
+    pause: bool,
+}
+
+impl Terminal {
+    pub fn new(mode: RenderMode) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Self { out, mode, palette: Palette::default(), shown: Vec::new(), releases, held_until: [None; NUM_KEYS], pause: false })
+    }
+
+    // Nothing is on screen, e.g. after the terminal was resized
+    pub fn needs_redraw(&self) -> bool {
+        self.shown.is_empty()
+    }
+
+    pub fn take_pause(&mut self) -> bool {
+        std::mem::take(&mut self.pause)
+    }
+
+    // Writes a status line under the screen
+    pub fn status(&mut self, text: &str) -> io::Result<()> {
+        queue!(self.out, MoveTo(0, self.shown.len() as u16), Clear(ClearType::CurrentLine))?;
+        write!(self.out, "{}", text)?;
+        self.out.flush()
+    }
+
+    fn draw(&mut self, gfx: &[u8], width: usize, height: usize) -> io::Result<()> {
+        let lines = render(gfx, width, height, self.mode, &self.palette.colors);
+        if lines.len() != self.shown.len() {
+            // Resolution changed, get rid of the old screen
+            queue!(self.out, Clear(ClearType::All))?;
+            self.shown.clear();
+        }
+        for (row, line) in lines.iter().enumerate() {
+            if self.shown.get(row) != Some(line) {
+                queue!(self.out, MoveTo(0, row as u16))?;
+                write!(self.out, "{}", line)?;
+            }
+        }
+        self.shown = lines;
+        self.out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl FrameSink for Terminal {
+    fn present(&mut self, gfx: &[u8], width: usize, height: usize) {
+        // Nothing sensible to do if the terminal went away
+        let _ = self.draw(gfx, width, height);
+    }
+}
+
+impl InputProvider for Terminal {
+    // Returns true on Esc or Ctrl+C
+    fn poll_keys(&mut self, keys: &mut [bool; NUM_KEYS]) -> bool {
+        let now = Instant::now();
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let key = match event::read() {
+                Ok(Event::Key(key)) => key,
+                // Redraw everything on the next frame
+                Ok(Event::Resize(..)) => {
+                    self.shown.clear();
+                    let _ = execute!(self.out, Clear(ClearType::All));
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(_) => return true,
+            };
+            let pressed = key.kind != KeyEventKind::Release;
+            match key.code {
+                KeyCode::Esc => return true,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+                KeyCode::Char('p') if key.kind == KeyEventKind::Press => self.pause = true,
+                KeyCode::Char(c) => {
+                    if let Some(k) = keypad(c) {
+                        keys[k] = pressed;
+                        self.held_until[k] = if self.releases { None } else { Some(now + KEY_HOLD) };
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !self.releases {
+            for (key, until) in keys.iter_mut().zip(self.held_until.iter_mut()) {
+                if until.is_some_and(|t| now >= t) {
+                    *key = false;
+                    *until = None;
+                }
+            }
+        }
+        false
+    }
+}
+
+// Rings the terminal bell when the buzzer starts
+#[derive(Debug, Default)]
+pub struct Bell {
+    pub enabled: bool,
+    on: bool,
+}
+
+impl Bell {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, on: false }
+    }
+}
+
+impl AudioSink for Bell {
+    fn set_beeping(&mut self, on: bool) {
+        if on && !self.on && self.enabled {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        self.on = on;
+    }
+}
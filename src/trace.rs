@@ -0,0 +1,122 @@
+// Instruction tracing. When enabled, the machine writes one line per executed
+// instruction in a fixed format so traces from two builds can be diffed:
+//
+//   0200 00E0 CLS
+//   0202 A22A LD I, 0x22A          V=00000000000000000000000000000000 I=022A DT=00 ST=00 SP=0
+//
+// PC and opcode are those of the instruction, the register dump (full level
+// only) is the machine state after it ran.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum TraceLevel {
+    #[default]
+    Off,
+    // PC, opcode and mnemonic
+    Opcode,
+    // Opcode level plus registers, I, timers and stack depth
+    Full,
+}
+
+impl TraceLevel {
+    pub const NAMES: [&'static str; 3] = ["off", "opcode", "full"];
+
+    pub fn from_name(name: &str) -> Option<TraceLevel> {
+        match name.to_lowercase().as_str() {
+            "off" => Some(TraceLevel::Off),
+            "opcode" => Some(TraceLevel::Opcode),
+            "full" => Some(TraceLevel::Full),
+            _ => None,
+        }
+    }
+
+    // The level after this one, wrapping back to off
+    pub fn next(self) -> TraceLevel {
+        match self {
+            TraceLevel::Off => TraceLevel::Opcode,
+            TraceLevel::Opcode => TraceLevel::Full,
+            TraceLevel::Full => TraceLevel::Off,
+        }
+    }
+}
+
+impl fmt::Display for TraceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(TraceLevel::NAMES[*self as usize])
+    }
+}
+
+// Machine state written at the full trace level
+pub struct TraceState<'a> {
+    pub v: &'a [u8],
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub sp: usize,
+}
+
+pub struct Tracer {
+    pub level: TraceLevel,
+    out: Box<dyn Write + Send>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer").field("level", &self.level).finish()
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::stderr(TraceLevel::Off)
+    }
+}
+
+impl Tracer {
+    pub fn stderr(level: TraceLevel) -> Self {
+        Self { level, out: Box::new(io::stderr()) }
+    }
+
+    // Traces to a file, truncating it
+    pub fn to_file(level: TraceLevel, path: &str) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { level, out: Box::new(BufWriter::new(file)) })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.level != TraceLevel::Off
+    }
+
+    // Writes the line for one instruction. Write errors are reported once
+    // and turn tracing off rather than stopping the emulator.
+    pub fn instruction(&mut self, pc: usize, opcode: u16, mnemonic: &str, state: TraceState) {
+        let result = if self.level == TraceLevel::Full {
+            let mut regs = String::with_capacity(state.v.len() * 2);
+            for vx in state.v {
+                regs.push_str(&format!("{:02X}", vx));
+            }
+            writeln!(self.out, "{:04X} {:04X} {:<20} V={} I={:04X} DT={:02X} ST={:02X} SP={:X}",
+                     pc, opcode, mnemonic, regs, state.i, state.dt, state.st, state.sp)
+        } else {
+            writeln!(self.out, "{:04X} {:04X} {}", pc, opcode, mnemonic)
+        };
+        if let Err(e) = result {
+            eprintln!("Could not write trace, tracing disabled: {}", e);
+            self.level = TraceLevel::Off;
+        }
+    }
+
+    // Notes a fault that ErrorPolicy::Ignore skipped. Written at every level,
+    // including off, so ignored faults still show up somewhere.
+    pub fn ignored(&mut self, error: &dyn fmt::Display) {
+        if let Err(e) = writeln!(self.out, "Ignoring {}", error) {
+            eprintln!("Could not write trace: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.out.flush().ok();
+    }
+}
@@ -0,0 +1,157 @@
+// Runs the chip8-headless binary and checks what it writes to stdout
+use std::collections::HashMap;
+use std::process::Command;
+
+#[derive(Debug, PartialEq)]
+enum Json {
+    Number(f64),
+    Text(String),
+    List(Vec<Json>),
+    Object(HashMap<String, Json>),
+}
+
+// Just enough of a JSON parser for the register dump. Fails on anything
+// that isn't valid JSON, which is the point.
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.text.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> Result<(), String> {
+        self.skip_space();
+        if self.text.get(self.pos) != Some(&c) {
+            return Err(format!("expected '{}' at byte {}", c as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.text.get(self.pos).copied()
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.eat(b'"')?;
+        let start = self.pos;
+        while self.text.get(self.pos) != Some(&b'"') {
+            if self.pos >= self.text.len() {
+                return Err("unterminated string".to_string());
+            }
+            self.pos += if self.text[self.pos] == b'\\' { 2 } else { 1 };
+        }
+        self.pos += 1;
+        Ok(String::from_utf8_lossy(&self.text[start..self.pos - 1]).into_owned())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = HashMap::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.eat(b':')?;
+                    fields.insert(key, self.value()?);
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.eat(b'}')?;
+                        return Ok(Json::Object(fields));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::List(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.eat(b']')?;
+                        return Ok(Json::List(items));
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::Text(self.string()?)),
+            Some(c) if c == b'-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.text.get(self.pos).is_some_and(|c| b"-+.eE".contains(c) || c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+                number.parse().map(Json::Number).map_err(|_| format!("bad number {}", number))
+            }
+            _ => Err(format!("unexpected input at byte {}", self.pos)),
+        }
+    }
+}
+
+fn parse_json(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    if parser.peek().is_some() {
+        return Err(format!("trailing input at byte {}", parser.pos));
+    }
+    Ok(value)
+}
+
+// Tests run in parallel, so each gives its ROM file its own name
+fn headless(name: &str, rom: &[u8], args: &[&str]) -> (Json, String) {
+    let path = std::env::temp_dir().join(format!("chip8-headless-{}-{}.ch8", name, std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-headless")).args(args).arg(&path).output().unwrap();
+    std::fs::remove_file(&path).ok();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let json = parse_json(&stdout).unwrap_or_else(|e| panic!("{}, stdout was:\n{}", e, stdout));
+    (json, String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> &'a Json {
+    match json {
+        Json::Object(fields) => fields.get(name).unwrap_or_else(|| panic!("no field {}", name)),
+        _ => panic!("not an object: {:?}", json),
+    }
+}
+
+#[test]
+fn hires_rom_gives_plain_json() {
+    // 1260 header, then at 0x2C0: V0 = 5 and a jump to itself
+    let mut rom = vec![0x12, 0x60];
+    rom.resize(0xC0, 0);
+    rom.extend_from_slice(&[0x60, 0x05, 0x12, 0xC2]);
+    let (json, _) = headless("hires", &rom, &["--frames", "3", "--stop-on-loop"]);
+    assert_eq!(field(&json, "stop"), &Json::Text("loop".to_string()));
+    assert_eq!(field(&json, "pc"), &Json::Number(0x2C2 as f64));
+    assert_eq!(field(&json, "width"), &Json::Number(64.0));
+    assert_eq!(field(&json, "height"), &Json::Number(64.0));
+    match field(&json, "v") {
+        Json::List(v) => assert_eq!(v[0], Json::Number(5.0)),
+        other => panic!("v is {:?}", other),
+    }
+}
+
+#[test]
+fn faults_are_reported_in_the_json() {
+    // Returns with nothing on the stack
+    let (json, stderr) = headless("fault", &[0x00, 0xEE], &[]);
+    assert_eq!(field(&json, "stop"), &Json::Text("fault".to_string()));
+    assert!(matches!(field(&json, "error"), Json::Text(message) if !message.is_empty()));
+    assert!(stderr.contains("Halted"));
+}